@@ -0,0 +1,211 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::identity;
+use crate::store::EventStore;
+
+/// Shows every app it matches as `name`.
+/// With both `title` and `exe` set either one is enough.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>, // a regex, tried on the app name and every raw title seen for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exe: Option<String>, // a file name like "Tekken8.exe", any case, the extension can be left off
+}
+
+fn same_exe(rule: &str, exe: &str) -> bool {
+    let path = Path::new(exe);
+    let name = path.file_name().map(|name| name.to_string_lossy());
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy());
+    [name, stem].into_iter().flatten().any(|exe| exe.eq_ignore_ascii_case(rule))
+}
+
+/// The rules from the settings, ready to use. The first rule to match wins.
+pub struct Aliases {
+    rules: Vec<(Rule, Option<Regex>)>,
+    names: Mutex<HashMap<String, String>>, // raw => canonical, apps dont change what they are
+}
+
+/// What a permanent rewrite would do to one app.
+#[derive(Serialize, Debug)]
+pub struct Rename {
+    from: String,
+    to: String,
+    events: u64,
+}
+
+impl Aliases {
+    pub fn compile(rules: &[Rule]) -> Result<Aliases, String> {
+        let mut compiled = Vec::with_capacity(rules.len());
+        for (i, rule) in rules.iter().enumerate() {
+            if rule.name.trim().is_empty() {
+                return Err(format!("alias rule {} has no name", i + 1));
+            }
+            if rule.title.is_none() && rule.exe.is_none() {
+                return Err(format!("alias rule {} ({}) needs a title or an exe to match", i + 1, rule.name));
+            }
+
+            let title = rule.title.as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|err| format!("alias rule {} ({}) has a bad title regex: {err}", i + 1, rule.name))?;
+            compiled.push((rule.clone(), title));
+        }
+
+        Ok(Aliases { rules: compiled, names: Mutex::new(HashMap::new()) })
+    }
+
+    /// What `app` shows up as, itself if no rule matches.
+    /// Apps from before identities were kept are stored under the raw title, those come out normalized like new ones.
+    pub fn canonical(&self, store: &dyn EventStore, app: &str) -> String {
+        if let Some(name) = self.names.lock().unwrap().get(app) {
+            return name.clone();
+        }
+
+        // an app from an import or before identities were kept has nothing known, only its name to go on
+        let known = store.known(app).ok().flatten();
+        let legacy = known.is_none();
+        let known = known.unwrap_or_default();
+        let name = self.rules.iter()
+            .find(|(rule, title)| {
+                let title = title.as_ref().is_some_and(|title| title.is_match(app) || known.titles.iter().any(|seen| title.is_match(seen)));
+                let exe = rule.exe.as_deref().is_some_and(|exe| same_exe(exe, app) || known.exe.as_deref().is_some_and(|known| same_exe(exe, known)));
+                title || exe
+            })
+            .map_or_else(|| if legacy { identity::normalize(app) } else { app.to_string() }, |(rule, _)| rule.name.clone());
+
+        self.names.lock().unwrap().insert(app.to_string(), name.clone());
+        name
+    }
+
+    /// Every stored app name since `start` that shows up as `app`, including `app` itself.
+    pub fn members(&self, store: &dyn EventStore, app: &str, start: u128) -> Result<Vec<String>, String> {
+        let mut members = vec![app.to_string()];
+        for count in store.counts(start, u128::MAX)? {
+            if !members.contains(&count.app) && self.canonical(store, &count.app) == app {
+                members.push(count.app);
+            }
+        }
+
+        Ok(members)
+    }
+
+    /// What `rewrite` would rename, with how many events each app has.
+    pub fn preview(&self, store: &dyn EventStore) -> Result<Vec<Rename>, String> {
+        let mut events = BTreeMap::<String, u64>::new();
+        for count in store.counts(0, u128::MAX)? {
+            *events.entry(count.app).or_default() += count.count;
+        }
+
+        Ok(events.into_iter()
+            .filter_map(|(from, events)| {
+                let to = self.canonical(store, &from);
+                (to != from).then_some(Rename { from, to, events })
+            })
+            .collect())
+    }
+
+    /// Moves every event to the name it shows up as, for good, downsampled ones too. Returns how many moved.
+    /// What was known about the old names stays under them, the rules dont need it once the events are moved.
+    pub fn rewrite(&self, store: &dyn EventStore) -> Result<usize, String> {
+        // worked out first, stores can be holding locks while they call back
+        let renames: HashMap<String, String> = self.preview(store)?.into_iter().map(|rename| (rename.from, rename.to)).collect();
+        if renames.is_empty() {
+            return Ok(0);
+        }
+
+        let moved = store.relabel(0, u128::MAX, &mut |event| renames.get(&event.app).cloned())?;
+        Ok(moved + store.relabel_downsampled(&mut |app| renames.get(app).cloned())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+    use crate::store::memory::MemoryStore;
+    use crate::store::tests::event;
+
+    const AT: u128 = 1_700_002_800_000;
+
+    fn rule(name: &str, title: Option<&str>, exe: Option<&str>) -> Rule {
+        Rule { name: name.to_string(), title: title.map(str::to_string), exe: exe.map(str::to_string) }
+    }
+
+    fn seen(store: &MemoryStore, app: &str, title: &str, exe: Option<&str>) {
+        store.identify(app, &Identity { exe: exe.map(str::to_string), ..Identity::titled(title) }).unwrap();
+    }
+
+    #[test]
+    fn exes_match_in_any_case_with_or_without_the_extension() {
+        assert!(same_exe("Tekken8.exe", "/games/tekken8/TEKKEN8.EXE"));
+        assert!(same_exe("tekken8", "/games/tekken8/Tekken8.exe"));
+        assert!(same_exe("tekken8", "Tekken8"));
+        assert!(!same_exe("Tekken", "/games/tekken8/Tekken8.exe"));
+        assert!(!same_exe("Tekken8.exe", "/games/tekken8/Tekken8.sh"));
+    }
+
+    #[test]
+    fn rules_match_titles_and_exes() {
+        let store = MemoryStore::default();
+        seen(&store, "TESV", "The Elder Scrolls V: Skyrim", Some("/games/skyrim/TESV.exe"));
+        seen(&store, "Polaris-Win64-Shipping", "TEKKEN 8", Some("/games/tekken8/Polaris-Win64-Shipping.exe"));
+        seen(&store, "Doom v1.2", "Doom v1.2", None);
+
+        let aliases = Aliases::compile(&[
+            rule("Skyrim", Some("(?i)skyrim"), None),
+            rule("Tekken 8", None, Some("polaris-win64-shipping.EXE")),
+        ]).unwrap();
+
+        // a raw title seen for the app is enough
+        assert_eq!(aliases.canonical(&store, "TESV"), "Skyrim");
+        assert_eq!(aliases.canonical(&store, "Polaris-Win64-Shipping"), "Tekken 8");
+        // the app name itself is tried too
+        assert_eq!(aliases.canonical(&store, "skyrim se"), "Skyrim");
+        // nothing known, so from before identities were kept, and normalized like a new one would be
+        assert_eq!(aliases.canonical(&store, "Celeste v1.4.0.0 - Paused"), "Celeste");
+        // known already means it was named the new way, its left as it is
+        assert_eq!(aliases.canonical(&store, "Doom v1.2"), "Doom v1.2");
+    }
+
+    #[test]
+    fn bad_rules_dont_compile() {
+        assert!(Aliases::compile(&[rule(" ", Some("x"), None)]).is_err());
+        assert!(Aliases::compile(&[rule("x", None, None)]).is_err());
+        assert!(Aliases::compile(&[rule("x", Some("("), None)]).is_err());
+    }
+
+    #[test]
+    fn rewriting_moves_what_preview_shows() {
+        let store = MemoryStore::default();
+        seen(&store, "TESV", "Skyrim", None);
+        seen(&store, "Other", "Other", None);
+        let connected = |at: u128, app: &str| event(at, app, gilrs::EventType::Connected);
+        store.append(&[connected(AT, "TESV"), connected(AT + 1, "Celeste v1.4.0.0"), connected(AT + 2, "Celeste v1.4.0.0"), connected(AT + 3, "Other")]).unwrap();
+
+        let aliases = Aliases::compile(&[rule("Skyrim", Some("Skyrim"), None)]).unwrap();
+        let renames: Vec<(String, String, u64)> = aliases.preview(&store).unwrap().into_iter().map(|rename| (rename.from, rename.to, rename.events)).collect();
+        assert_eq!(renames, vec![
+            ("Celeste v1.4.0.0".to_string(), "Celeste".to_string(), 2),
+            ("TESV".to_string(), "Skyrim".to_string(), 1),
+        ]);
+
+        assert_eq!(aliases.rewrite(&store).unwrap(), 3);
+        let mut apps = Vec::new();
+        store.scan(0, u128::MAX, &mut |event| {
+            apps.push(event.app);
+            true
+        }).unwrap();
+        assert_eq!(apps, vec!["Other", "Celeste", "Celeste", "Skyrim"]);
+
+        // done once is done
+        assert!(aliases.preview(&store).unwrap().is_empty());
+        assert_eq!(aliases.rewrite(&store).unwrap(), 0);
+    }
+}
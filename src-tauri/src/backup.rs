@@ -0,0 +1,139 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rocksdb::backup::{BackupEngine, BackupEngineOptions, RestoreOptions};
+use rocksdb::{Env, DB};
+use serde::{Deserialize, Serialize};
+
+use crate::paths::Paths;
+use crate::store::{self, EventStore};
+use crate::UserSettings;
+
+// how often the schedule checks if a backup is due
+const CHECK_EVERY: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Backups {
+    every_hours: u32, // 0 only backs up when asked
+    pub keep: u32,    // 0 keeps every backup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>, // coca-backups in the data dir if not set, another disk is safer
+}
+
+impl Default for Backups {
+    fn default() -> Self {
+        Backups { every_hours: 24, keep: 7, dir: None }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Backup {
+    id: u32,
+    at: i64, // unix seconds
+    size: u64,
+    files: u32,
+}
+
+fn engine(dir: &str) -> Result<BackupEngine, String> {
+    // rocks only makes the last dir, a setting can point a few levels into a new disk
+    std::fs::create_dir_all(dir).map_err(|err| format!("failed to make {dir}: {err}"))?;
+    let opts = BackupEngineOptions::new(dir).map_err(|err| err.to_string())?;
+    let env = Env::new().map_err(|err| err.to_string())?;
+    BackupEngine::open(&opts, &env).map_err(|err| err.to_string())
+}
+
+/// Backs up the db as it is right now, then drops all but the newest `keep`.
+pub fn create(db: &DB, dir: &str, keep: u32) -> Result<Backup, String> {
+    let mut engine = engine(dir)?;
+    // flush first, so the backup doesnt need the wal to be whole
+    engine.create_new_backup_flush(db, true).map_err(|err| err.to_string())?;
+
+    if keep > 0 {
+        engine.purge_old_backups(keep as usize).map_err(|err| err.to_string())?;
+    }
+
+    list(dir)?.into_iter().next().ok_or_else(|| "backup went missing".to_string())
+}
+
+/// Every backup in `dir`, newest first.
+pub fn list(dir: &str) -> Result<Vec<Backup>, String> {
+    let mut backups: Vec<Backup> = engine(dir)?.get_backup_info().into_iter()
+        .map(|info| Backup { id: info.backup_id, at: info.timestamp, size: info.size, files: info.num_files })
+        .collect();
+
+    backups.sort_by(|a, b| b.id.cmp(&a.id));
+    Ok(backups)
+}
+
+/// Replaces the db at `path` with backup `id`, swapping it in under everything that is running.
+/// The db being replaced is moved aside rather than deleted, the returned path says where to.
+pub fn restore(store: &store::Swap, dir: &str, id: u32, path: &str) -> Result<String, String> {
+    let mut engine = engine(dir)?;
+    engine.verify_backup(id).map_err(|err| format!("backup {id} is damaged: {err}"))?;
+
+    // restore next to the db first, so a bad backup never touches the live one
+    let staging = format!("{path}.restoring");
+    if std::path::Path::new(&staging).exists() {
+        std::fs::remove_dir_all(&staging).map_err(|err| err.to_string())?;
+    }
+
+    engine.restore_from_backup(&staging, &staging, &RestoreOptions::default(), id).map_err(|err| err.to_string())?;
+    drop(store::rocks::open_db(&staging)?);
+
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_secs();
+    let aside = format!("{path}.before-restore-{secs}");
+
+    let mut result = Ok(aside.clone());
+    store.replace(|old| {
+        // the swap holds the only handle, so this closes the db
+        drop(old);
+
+        let swapped = std::fs::rename(path, &aside).map_err(|err| err.to_string())
+            .and_then(|_| std::fs::rename(&staging, path).map_err(|err| {
+                // put the old one back where it was
+                if let Err(err) = std::fs::rename(&aside, path) {
+                    log::error!("failed to move {aside} back to {path}: {err}");
+                }
+                err.to_string()
+            }));
+
+        if let Err(err) = swapped {
+            result = Err(err);
+        }
+
+        match store::rocks::RocksStore::open(path) {
+            Ok(store) => Arc::new(store),
+            Err(err) => {
+                // nothing left to fall back on, keep capturing so at least this session isnt lost
+                log::error!("failed to open {path} after restoring, keeping events in memory: {err}");
+                result = Err(err);
+                Arc::new(store::memory::MemoryStore::default())
+            }
+        }
+    });
+
+    result
+}
+
+/// Backs up every `every_hours`, counting from the newest backup so restarts dont add extra ones.
+/// rocks BackupEngine keeps them all in one dir, sharing the files they have in common.
+pub fn spawn(store: Arc<dyn EventStore>, settings: Arc<Mutex<UserSettings>>, paths: Paths) {
+    std::thread::spawn(move || loop {
+        let backups = settings.lock().unwrap().backups.clone();
+        let (every_hours, keep, dir) = (backups.every_hours, backups.keep, paths.backups(&backups));
+        if every_hours > 0 {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_secs() as i64;
+            let last = list(&dir).ok().and_then(|backups| backups.first().map(|backup| backup.at)).unwrap_or(0);
+
+            if now - last >= every_hours as i64 * 60 * 60 {
+                match store.backup(&dir, keep) {
+                    Ok(backup) => log::info!("backed up the db: {backup:?}"),
+                    Err(err) => log::error!("failed to back up the db: {err}"),
+                }
+            }
+        }
+
+        std::thread::sleep(CHECK_EVERY);
+    });
+}
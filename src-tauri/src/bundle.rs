@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::aliases::Aliases;
+use crate::store::EventStore;
+use crate::{app_stats_for, span, AppStats, DAY};
+
+// a bundle is MAGIC, the version, then a bincode Bundle
+// anything that changes the layout of Bundle needs a new version, and open needs to keep reading the old ones
+const MAGIC: &[u8; 4] = b"COCA";
+const BUNDLE_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Meta {
+    name: String, // what the sharer called the game, never the window title
+    timeframe: String,
+    from: u128, // ms since the unix epoch
+    to: u128,
+    coca: String,     // the version that made it
+    platform: String, // button names are the same everywhere, codes are not
+}
+
+#[derive(Serialize, Deserialize)]
+struct Day {
+    start: u128, // utc midnight, ms since the unix epoch
+    events: u64,
+}
+
+/// One app's stats, sized to send to a friend.
+/// Only counts go in, no raw events, window titles or controller names.
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    version: u8,
+    meta: Meta,
+    stats: AppStats,
+    daily: Vec<Day>, // oldest first
+}
+
+/// Bundles `app` over `timeframe`, shown as `name`.
+/// `app` is a name from applications, so everything aliased to it goes in.
+pub fn create(store: &dyn EventStore, aliases: &Aliases, app: &str, name: &str, timeframe: &str) -> Result<Bundle, String> {
+    if name.trim().is_empty() {
+        return Err("a bundle needs a name to show instead of the window title".to_string());
+    }
+
+    let to = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis();
+    let from = to - span(timeframe);
+
+    let mut stats = app_stats_for(store, aliases, app.to_string(), timeframe, None)?;
+    stats.name = name.to_string();
+
+    let mut daily = BTreeMap::<u128, u64>::new();
+    for count in store.counts(from, DAY)?.into_iter().filter(|count| aliases.canonical(store, &count.app) == app) {
+        *daily.entry(count.bucket - count.bucket % DAY).or_default() += count.count;
+    }
+
+    Ok(Bundle {
+        version: BUNDLE_VERSION,
+        meta: Meta {
+            name: name.to_string(),
+            timeframe: timeframe.to_string(),
+            from,
+            to,
+            coca: env!("CARGO_PKG_VERSION").to_string(),
+            platform: std::env::consts::OS.to_string(),
+        },
+        stats,
+        daily: daily.into_iter().map(|(start, events)| Day { start, events }).collect(),
+    })
+}
+
+impl Bundle {
+    // a bundle comes from someone else, so anything that couldnt have come out of create is refused
+    fn check(&self) -> Result<(), String> {
+        if self.version != BUNDLE_VERSION {
+            return Err(format!("says its version {} inside", self.version));
+        }
+        if self.meta.from > self.meta.to {
+            return Err("ends before it starts".to_string());
+        }
+
+        let first = self.meta.from - self.meta.from % DAY;
+        let mut last = None;
+        for day in &self.daily {
+            if day.start % DAY != 0 || day.start < first || day.start > self.meta.to || last.is_some_and(|last| day.start <= last) {
+                return Err(format!("has a day at {} outside of {}..{} or out of order", day.start, self.meta.from, self.meta.to));
+            }
+            last = Some(day.start);
+        }
+
+        let presses = self.stats.presses.iter().map(|button| button.presses);
+        let axes = self.stats.axes.iter().flat_map(|axis| axis.pos_buckets.values().copied());
+        let combos = self.stats.combos.iter().map(|combo| combo.presses);
+        if presses.chain(axes).chain(combos).any(|n| n < 0) {
+            return Err("has negative counts".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+pub fn save(bundle: &Bundle, path: &str) -> Result<(), String> {
+    let mut file = std::fs::File::create(path).map_err(|err| format!("failed to create {path}: {err}"))?;
+    file.write_all(MAGIC).and_then(|_| file.write_all(&[BUNDLE_VERSION])).map_err(|err| format!("failed to write {path}: {err}"))?;
+    bincode::serialize_into(&mut file, bundle).map_err(|err| format!("failed to write {path}: {err}"))
+}
+
+/// Reads a bundle, nothing from it goes near the store.
+pub fn open(path: &str) -> Result<Bundle, String> {
+    let mut file = std::fs::File::open(path).map_err(|err| format!("failed to open {path}: {err}"))?;
+
+    let mut header = [0u8; 5];
+    file.read_exact(&mut header).map_err(|_| format!("{path} is too short to be a bundle"))?;
+    if &header[..4] != MAGIC {
+        return Err(format!("{path} isnt a coca bundle"));
+    }
+
+    if header[4] != BUNDLE_VERSION {
+        return Err(format!("{path} is bundle version {}, this coca reads up to {BUNDLE_VERSION}", header[4]));
+    }
+
+    // same encoding as serialize_into, limited to the file so a bad length cant ask for more memory than that
+    let limit = file.metadata().map_err(|err| format!("failed to read {path}: {err}"))?.len();
+    let bundle: Bundle = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+        .deserialize_from(file)
+        .map_err(|err| format!("{path} is damaged: {err}"))?;
+
+    bundle.check().map_err(|err| format!("{path} is damaged, it {err}"))?;
+    Ok(bundle)
+}
@@ -0,0 +1,174 @@
+use std::fmt::Write;
+
+use crate::aliases::Aliases;
+use crate::store::EventStore;
+use crate::{app_stats_for, applications_for, graph_for, AppStats, Application, Point};
+
+// the outline the heatmap goes on, a raster inside an svg so it renders the same everywhere
+const PS5: &str = include_str!("../../static/ps5.svg");
+
+const WIDTH: u32 = 1200;
+const HEIGHT: u32 = 630;
+const TOP_APPS: usize = 5;
+
+// where each button sits on ps5.svg, which is 832x527
+const BUTTONS: &[(gilrs::Button, f32, f32)] = &[
+    (gilrs::Button::North, 656.0, 98.0),
+    (gilrs::Button::South, 656.0, 212.0),
+    (gilrs::Button::West, 599.0, 155.0),
+    (gilrs::Button::East, 712.0, 155.0),
+    (gilrs::Button::DPadUp, 174.0, 118.0),
+    (gilrs::Button::DPadDown, 174.0, 192.0),
+    (gilrs::Button::DPadLeft, 135.0, 155.0),
+    (gilrs::Button::DPadRight, 212.0, 155.0),
+    (gilrs::Button::LeftThumb, 290.0, 260.0),
+    (gilrs::Button::RightThumb, 540.0, 260.0),
+    (gilrs::Button::Mode, 415.0, 255.0),
+    (gilrs::Button::Select, 240.0, 45.0),
+    (gilrs::Button::Start, 590.0, 45.0),
+    (gilrs::Button::LeftTrigger, 175.0, 22.0),
+    (gilrs::Button::RightTrigger, 655.0, 22.0),
+    (gilrs::Button::LeftTrigger2, 115.0, 45.0),
+    (gilrs::Button::RightTrigger2, 715.0, 45.0),
+];
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// window titles can be anything, keep them to one line
+fn clip(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+
+    text.chars().take(max - 1).chain(std::iter::once('…')).collect()
+}
+
+/// The card as svg, from what the applications, graph and app_stats commands return.
+pub fn svg(timeframe: &str, apps: &[Application], points: &[Point], stats: &AppStats) -> String {
+    let mut svg = String::new();
+    let _ = write!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" viewBox="0 0 {WIDTH} {HEIGHT}">"#);
+    let _ = write!(svg, r##"<rect width="100%" height="100%" rx="24" fill="#ffffff" stroke="#dee2e6" stroke-width="2"/>"##);
+    let _ = write!(svg, r##"<g font-family="sans-serif" fill="#212529">"##);
+
+    // headline
+    let total: i64 = apps.iter().map(|app| app.presses as i64).sum();
+    let _ = write!(svg, r#"<text x="40" y="70" font-size="40" font-weight="bold">My {} in Coca</text>"#, escape(timeframe));
+    // applications count every kind of event, not only presses
+    let _ = write!(svg, r##"<text x="40" y="120" font-size="28" fill="#6c757d">{total} events</text>"##);
+
+    // top apps
+    let mut top: Vec<&Application> = apps.iter().collect();
+    top.sort_by(|a, b| b.presses.cmp(&a.presses).then_with(|| a.name.cmp(&b.name)));
+    for (i, app) in top.iter().take(TOP_APPS).enumerate() {
+        let y = 180 + i * 44;
+        let _ = write!(svg, r#"<text x="40" y="{y}" font-size="26">{}. {}</text>"#, i + 1, escape(&clip(&app.name, 26)));
+        let _ = write!(svg, r##"<text x="520" y="{y}" font-size="26" text-anchor="end" fill="#6c757d">{}</text>"##, app.presses);
+    }
+
+    // sparkline over the graph points
+    let (x, y, w, h) = (40.0, 450.0, 480.0, 130.0);
+    let max = points.iter().map(|point| point.data).max().unwrap_or(0).max(1) as f32;
+    let step = w / (points.len().max(2) - 1) as f32;
+    let line: Vec<String> = points.iter().enumerate()
+        .map(|(i, point)| format!("{:.1},{:.1}", x + i as f32 * step, y + h - point.data as f32 / max * h))
+        .collect();
+    let _ = write!(svg, r##"<line x1="{x}" y1="{}" x2="{}" y2="{}" stroke="#dee2e6" stroke-width="2"/>"##, y + h, x + w, y + h);
+    if !line.is_empty() {
+        let _ = write!(svg, r##"<polyline points="{}" fill="none" stroke="#0d6efd" stroke-width="4" stroke-linejoin="round"/>"##, line.join(" "));
+    }
+
+    let _ = write!(svg, "</g>");
+
+    // heatmap on the controller, the busiest button is fully red
+    let _ = write!(svg, r#"<g transform="translate(560 150) scale(0.75)">{PS5}"#);
+    let max = stats.presses.iter().map(|button| button.presses).max().unwrap_or(0).max(1) as f32;
+    for (button, bx, by) in BUTTONS {
+        let presses = stats.presses.iter().find(|press| press.name == *button).map_or(0, |press| press.presses);
+        if presses > 0 {
+            let _ = write!(svg, r##"<circle cx="{bx}" cy="{by}" r="26" fill="#dc3545" fill-opacity="{:.2}"/>"##, 0.15 + 0.7 * presses as f32 / max);
+        }
+    }
+    let _ = write!(svg, "</g></svg>");
+
+    svg
+}
+
+/// Rasterizes a card, without a window or a gpu so it works on a headless box.
+pub fn png(svg: &str) -> Result<Vec<u8>, String> {
+    use resvg::{tiny_skia, usvg};
+
+    let mut opt = usvg::Options::default();
+    opt.fontdb_mut().load_system_fonts();
+    if opt.fontdb.is_empty() {
+        log::warn!("no system fonts found, the card will have no text");
+    }
+
+    let tree = usvg::Tree::from_str(svg, &opt).map_err(|err| err.to_string())?;
+    let mut pixmap = tiny_skia::Pixmap::new(WIDTH, HEIGHT).ok_or("failed to make the image")?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+    pixmap.encode_png().map_err(|err| err.to_string())
+}
+
+/// Builds the card for `timeframe` and writes it to `path`, png or svg by the extension.
+/// With no `app` the heatmap is every app added up.
+pub fn render(store: &dyn EventStore, aliases: &Aliases, timeframe: &str, app: Option<&str>, path: &str) -> Result<(), String> {
+    let apps = applications_for(store, aliases, timeframe)?;
+    let points = graph_for(store, timeframe)?;
+
+    let stats = match app {
+        Some(app) => app_stats_for(store, aliases, app.to_string(), timeframe, None)?,
+        None => {
+            let mut all = AppStats { name: String::new(), presses: Vec::new(), axes: Vec::new(), combos: Vec::new() };
+            for app in &apps {
+                for button in app_stats_for(store, aliases, app.name.clone(), timeframe, None)?.presses {
+                    all.press(button.name, button.presses);
+                }
+            }
+            all
+        }
+    };
+
+    let svg = svg(timeframe, &apps, &points, &stats);
+    let bytes = if path.ends_with(".png") { png(&svg)? } else { svg.into_bytes() };
+    std::fs::write(path, bytes).map_err(|err| format!("failed to write {path}: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Button;
+
+    fn card() -> String {
+        let apps = vec![Application { name: "game & <friends>".to_string(), controller: "pad".to_string(), presses: 12, combos: 0, controllers: Vec::new() }];
+        let points = (0..24).map(|i| Point { data: i % 5, at: i as u128, label: i.to_string() }).collect::<Vec<_>>();
+        let stats = AppStats {
+            name: String::new(),
+            presses: vec![Button { name: gilrs::Button::South, presses: 12 }],
+            axes: Vec::new(),
+            combos: Vec::new(),
+        };
+        svg("week", &apps, &points, &stats)
+    }
+
+    #[test]
+    fn svg_is_the_card_size() {
+        let svg = card();
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="1200" height="630""#));
+        assert!(svg.contains("12 events"));
+        assert!(svg.contains("game &amp; &lt;friends&gt;"));
+        assert!(svg.ends_with("</g></svg>"));
+    }
+
+    #[test]
+    fn png_renders_without_a_window() {
+        let png = png(&card()).unwrap();
+
+        // the width and height are the first thing in the IHDR chunk
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), WIDTH);
+        assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), HEIGHT);
+    }
+}
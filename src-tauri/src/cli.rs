@@ -0,0 +1,37 @@
+use chrono::prelude::*;
+
+/// The value after `name`, as `--flag value` or `--flag=value`.
+pub fn value(name: &str) -> Option<String> {
+    let mut args = std::env::args();
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return args.next();
+        }
+
+        if let Some(value) = arg.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+
+    None
+}
+
+/// A flag without a value, like `--dry-run-migrations`.
+pub fn has(name: &str) -> bool {
+    std::env::args().any(|arg| arg == name)
+}
+
+/// ms since the unix epoch, a date (midnight utc) or an rfc 3339 time.
+pub fn parse_time(time: &str) -> Result<u128, String> {
+    if let Ok(ms) = time.parse::<u128>() {
+        return Ok(ms);
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(time, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp_millis().max(0) as u128);
+    }
+
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.timestamp_millis().max(0) as u128)
+        .map_err(|_| format!("{time:?} isnt ms, a date like 2024-07-21 or an rfc 3339 time"))
+}
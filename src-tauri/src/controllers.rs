@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocksdb::DB;
+use serde::{Deserialize, Serialize};
+
+use crate::store::EventStore;
+
+// every controller ever connected
+// key: Controller.id
+// value: json Controller
+pub const CONTROLLERS_CF: &str = "controllers";
+
+/// A controller as it is remembered between connections, events carry its `id` as their pad.
+/// The id is the model plus the order it connected in, not the pad itself: gilrs 0.10 gives no serial
+/// or device path, so with two of one model connected, which is -2 depends on who connected second.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Controller {
+    pub id: String,   // the uuid, with -2, -3, ... for more of the same model at once, see connect
+    pub uuid: String, // from gilrs, the same for every pad of a model on a platform
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+    pub name: String, // as the os or the sdl mapping has it
+    pub nickname: Option<String>,
+    pub first_seen: u128, // ms since the unix epoch
+    pub last_seen: u128,
+}
+
+impl Controller {
+    /// What to show for it, the nickname if it has one.
+    pub fn label(&self) -> String {
+        self.nickname.clone().unwrap_or_else(|| self.name.clone())
+    }
+}
+
+// 8-4-4-4-12 hex, how sdl and everyone else writes them
+fn uuid(bytes: [u8; 16]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Works out who `gamepad` is and remembers it, `connected` is every pad already given an id.
+/// Two of the same model cant be told apart, so the second one to connect is -2 each time.
+/// Nothing gilrs gives us stays put for one pad (the uuid is the model and there is no serial or path),
+/// so if they connect the other way round their events, stats and nicknames swap too.
+pub fn connect(store: &dyn EventStore, gamepad: &gilrs::Gamepad, connected: &HashMap<gilrs::GamepadId, String>) -> Controller {
+    let uuid = uuid(gamepad.uuid());
+    let mut id = uuid.clone();
+    let mut n = 1;
+    while connected.iter().any(|(other, taken)| *other != gamepad.id() && *taken == id) {
+        n += 1;
+        id = format!("{uuid}-{n}");
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis();
+    let known = store.controllers().unwrap_or_default().into_iter().find(|controller| controller.id == id);
+    let controller = Controller {
+        id,
+        uuid,
+        vendor: gamepad.vendor_id(),
+        product: gamepad.product_id(),
+        name: gamepad.name().to_string(),
+        nickname: known.as_ref().and_then(|known| known.nickname.clone()),
+        first_seen: known.as_ref().map_or(now, |known| known.first_seen),
+        last_seen: now,
+    };
+
+    if let Err(err) = store.save_controller(&controller) {
+        log::error!("failed to remember controller {}: {err}", controller.id);
+    }
+    controller
+}
+
+/// Gives the controller `id` a nickname, or takes it away with None.
+/// With more than one of a model the nickname goes with the id, so it follows whichever connects in that place.
+pub fn rename(store: &dyn EventStore, id: &str, nickname: Option<String>) -> Result<Controller, String> {
+    let mut controller = store.controllers()?.into_iter()
+        .find(|controller| controller.id == id)
+        .ok_or_else(|| format!("no controller {id}"))?;
+
+    controller.nickname = nickname.map(|nickname| nickname.trim().to_string()).filter(|nickname| !nickname.is_empty());
+    store.save_controller(&controller)?;
+    Ok(controller)
+}
+
+/// Pad => what to show for it. Pads from before controllers were kept are their name already.
+pub fn labels(store: &dyn EventStore) -> Result<HashMap<String, String>, String> {
+    Ok(store.controllers()?.into_iter().map(|controller| (controller.id.clone(), controller.label())).collect())
+}
+
+pub fn save(db: &DB, controller: &Controller) -> Result<(), String> {
+    let cf = db.cf_handle(CONTROLLERS_CF).ok_or("missing controllers column family")?;
+    let value = serde_json::to_vec(controller).map_err(|err| err.to_string())?;
+    db.put_cf(cf, controller.id.as_bytes(), value).map_err(|err| err.to_string())
+}
+
+pub fn all(db: &DB) -> Result<Vec<Controller>, String> {
+    let cf = db.cf_handle(CONTROLLERS_CF).ok_or("missing controllers column family")?;
+
+    let mut controllers = Vec::new();
+    for item in db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+        let (key, value) = item.map_err(|err| err.to_string())?;
+        match serde_json::from_slice(&value) {
+            Ok(controller) => controllers.push(controller),
+            Err(err) => log::warn!("skipping controller {}: {err}", String::from_utf8_lossy(&key)),
+        }
+    }
+
+    Ok(controllers)
+}
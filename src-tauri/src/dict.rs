@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use rocksdb::{WriteBatch, DB};
+
+// names are repeated on every rock, so store them once and give the rocks an id
+// [kind, '>', name] => id (big endian u32)
+// [kind, '<', id] => name
+// [kind, '#'] => next id
+// [kind, '@', id] => json, anything else known about the name, see identity::Known for apps
+pub const DICT_CF: &str = "dict";
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Kind {
+    App = 0,
+    Pad = 1,
+}
+
+fn forward(kind: Kind, name: &str) -> Vec<u8> {
+    let mut key = vec![kind as u8, b'>'];
+    key.extend_from_slice(name.as_bytes());
+    key
+}
+
+fn reverse(kind: Kind, id: u32) -> Vec<u8> {
+    let mut key = vec![kind as u8, b'<'];
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn about(kind: Kind, id: u32) -> Vec<u8> {
+    let mut key = vec![kind as u8, b'@'];
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn counter(kind: Kind) -> [u8; 2] {
+    [kind as u8, b'#']
+}
+
+fn decode_id(value: &[u8]) -> Result<u32, String> {
+    value.try_into().map(u32::from_be_bytes).map_err(|_| "bad dictionary id".to_string())
+}
+
+#[derive(Default)]
+struct Cache {
+    ids: HashMap<(Kind, String), u32>,
+    names: HashMap<(Kind, u32), String>,
+    next: HashMap<Kind, u32>,
+}
+
+/// Hands out ids for names and turns them back, caching both ways.
+/// There should only be one per db, otherwise two of them could hand out the same id.
+#[derive(Default)]
+pub struct Dict {
+    cache: std::sync::Mutex<Cache>,
+    dry_run: bool, // hand out ids, but never write them
+}
+
+impl Dict {
+    pub fn dry_run() -> Dict {
+        Dict { dry_run: true, ..Default::default() }
+    }
+
+    /// The id for `name`, making a new one if it has never been seen.
+    pub fn intern(&self, db: &DB, kind: Kind, name: &str) -> Result<u32, String> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(id) = cache.ids.get(&(kind, name.to_string())) {
+            return Ok(*id);
+        }
+
+        let cf = db.cf_handle(DICT_CF).ok_or("missing dict column family")?;
+        if let Some(id) = db.get_cf(cf, forward(kind, name)).map_err(|err| err.to_string())? {
+            let id = decode_id(&id)?;
+            cache.ids.insert((kind, name.to_string()), id);
+            return Ok(id);
+        }
+
+        let id = match cache.next.get(&kind) {
+            Some(id) => *id,
+            None => match db.get_cf(cf, counter(kind)).map_err(|err| err.to_string())? {
+                Some(id) => decode_id(&id)?,
+                None => 0,
+            },
+        };
+
+        // written straight away, a name without a rock is harmless but a rock without a name isnt
+        if !self.dry_run {
+            let mut batch = WriteBatch::default();
+            batch.put_cf(cf, forward(kind, name), id.to_be_bytes());
+            batch.put_cf(cf, reverse(kind, id), name.as_bytes());
+            batch.put_cf(cf, counter(kind), (id + 1).to_be_bytes());
+            db.write(batch).map_err(|err| err.to_string())?;
+        }
+
+        cache.next.insert(kind, id + 1);
+        cache.ids.insert((kind, name.to_string()), id);
+        cache.names.insert((kind, id), name.to_string());
+        Ok(id)
+    }
+
+    /// The id for `name` if it has one, without making one.
+    pub fn lookup(&self, db: &DB, kind: Kind, name: &str) -> Result<Option<u32>, String> {
+        if let Some(id) = self.cache.lock().unwrap().ids.get(&(kind, name.to_string())) {
+            return Ok(Some(*id));
+        }
+
+        let cf = db.cf_handle(DICT_CF).ok_or("missing dict column family")?;
+        match db.get_cf(cf, forward(kind, name)).map_err(|err| err.to_string())? {
+            Some(id) => Ok(Some(decode_id(&id)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Whatever was last said about `id` with `describe`.
+    pub fn described(&self, db: &DB, kind: Kind, id: u32) -> Result<Option<Vec<u8>>, String> {
+        let cf = db.cf_handle(DICT_CF).ok_or("missing dict column family")?;
+        db.get_cf(cf, about(kind, id)).map_err(|err| err.to_string())
+    }
+
+    pub fn describe(&self, db: &DB, kind: Kind, id: u32, value: &[u8]) -> Result<(), String> {
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let cf = db.cf_handle(DICT_CF).ok_or("missing dict column family")?;
+        db.put_cf(cf, about(kind, id), value).map_err(|err| err.to_string())
+    }
+
+    /// The name behind `id`, or "?" if it was never written.
+    pub fn name(&self, db: &DB, kind: Kind, id: u32) -> Result<String, String> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(name) = cache.names.get(&(kind, id)) {
+            return Ok(name.clone());
+        }
+
+        let cf = db.cf_handle(DICT_CF).ok_or("missing dict column family")?;
+        let name = match db.get_cf(cf, reverse(kind, id)).map_err(|err| err.to_string())? {
+            Some(name) => String::from_utf8_lossy(&name).to_string(),
+            None => {
+                log::warn!("no name for {kind:?} {id}");
+                return Ok("?".to_string());
+            }
+        };
+
+        cache.names.insert((kind, id), name.clone());
+        Ok(name)
+    }
+}
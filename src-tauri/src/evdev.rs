@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use gilrs::{Axis, Button, EventType};
+use serde::Serialize;
+
+use crate::jsonl::{Malformed, BATCH_SIZE};
+use crate::store::{Event, EventStore};
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_ABS: u16 = 0x03;
+
+// the evdev codes gilrs maps on linux, from linux/input-event-codes.h
+const BUTTONS: &[(u16, Button)] = &[
+    (0x130, Button::South), // BTN_SOUTH
+    (0x131, Button::East),
+    (0x132, Button::C),
+    (0x133, Button::North),
+    (0x134, Button::West),
+    (0x135, Button::Z),
+    (0x136, Button::LeftTrigger), // BTN_TL
+    (0x137, Button::RightTrigger),
+    (0x138, Button::LeftTrigger2),
+    (0x139, Button::RightTrigger2),
+    (0x13a, Button::Select),
+    (0x13b, Button::Start),
+    (0x13c, Button::Mode),
+    (0x13d, Button::LeftThumb),
+    (0x13e, Button::RightThumb),
+    (0x220, Button::DPadUp), // BTN_DPAD_UP
+    (0x221, Button::DPadDown),
+    (0x222, Button::DPadLeft),
+    (0x223, Button::DPadRight),
+];
+
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_Z: u16 = 0x02;
+const ABS_RX: u16 = 0x03;
+const ABS_RY: u16 = 0x04;
+const ABS_RZ: u16 = 0x05;
+const ABS_HAT0X: u16 = 0x10;
+const ABS_HAT0Y: u16 = 0x11;
+
+/// One line of a recording, whichever tool wrote it.
+struct Input {
+    time: u128, // us, since the epoch for evtest, since the recording started for evemu
+    kind: u16,
+    code: u16,
+    value: i32,
+}
+
+/// The min and max an axis reports, from the header.
+#[derive(Clone, Copy)]
+struct Range {
+    min: i32,
+    max: i32,
+}
+
+impl Range {
+    // what pads usually report when the header doesnt say
+    fn default_for(code: u16) -> Range {
+        match code {
+            ABS_Z | ABS_RZ => Range { min: 0, max: 255 },
+            ABS_HAT0X | ABS_HAT0Y => Range { min: -1, max: 1 },
+            _ => Range { min: -32768, max: 32767 },
+        }
+    }
+
+    // 0 to 1
+    fn unit(&self, value: i32) -> f32 {
+        if self.max <= self.min {
+            return 0.0;
+        }
+        ((value.clamp(self.min, self.max) - self.min) as f32 / (self.max - self.min) as f32).clamp(0.0, 1.0)
+    }
+
+    // -1 to 1
+    fn signed(&self, value: i32) -> f32 {
+        self.unit(value) * 2.0 - 1.0
+    }
+}
+
+/// A parsed evtest or evemu-record dump.
+struct Recording {
+    name: Option<String>,
+    ranges: HashMap<u16, Range>,
+    inputs: Vec<(usize, Input)>, // with the line each came from, for the report
+}
+
+// "1700000000.123456" to us, without going through a float
+fn micros(time: &str) -> Result<u128, String> {
+    let (secs, frac) = time.split_once('.').unwrap_or((time, "0"));
+    // checked first, a multibyte character would make the slice below panic
+    if !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("{time:?} isnt a time"));
+    }
+    let frac = format!("{frac:0<6}");
+    Ok(secs.parse::<u128>().map_err(|_| format!("{time:?} isnt a time"))? * 1_000_000
+        + frac[..6].parse::<u128>().map_err(|_| format!("{time:?} isnt a time"))?)
+}
+
+fn hex(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text.trim_start_matches("0x"), 16).map_err(|_| format!("{text:?} isnt a hex code"))
+}
+
+// "type 3 (EV_ABS)" or "code 0 (ABS_X)" to 3 or 0
+fn numbered<'a>(part: &'a str, name: &str) -> Option<&'a str> {
+    part.trim().strip_prefix(name)?.split_whitespace().next()
+}
+
+// E: <sec>.<usec> <type hex> <code hex> <value>
+fn evemu(line: &str) -> Result<Input, String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let [time, kind, code, value] = parts[..] else {
+        return Err("an evemu event needs a time, type, code and value".to_string());
+    };
+
+    Ok(Input { time: micros(time)?, kind: hex(kind)?, code: hex(code)?, value: value.parse().map_err(|_| format!("{value:?} isnt a value"))? })
+}
+
+// Event: time <sec>.<usec>, type 3 (EV_ABS), code 0 (ABS_X), value -1234
+// or Event: time <sec>.<usec>, -------------- SYN_REPORT ------------ which is None
+fn evtest(line: &str) -> Result<Option<Input>, String> {
+    let parts: Vec<&str> = line.split(',').collect();
+    let [time, kind, code, value] = parts[..] else {
+        return if parts.len() == 2 { Ok(None) } else { Err("an evtest event needs a time, type, code and value".to_string()) };
+    };
+
+    let kind = numbered(kind, "type").ok_or("an evtest event needs a type")?;
+    let code = numbered(code, "code").ok_or("an evtest event needs a code")?;
+    let value = numbered(value, "value").ok_or("an evtest event needs a value")?;
+    Ok(Some(Input {
+        time: micros(time.trim())?,
+        kind: kind.parse().map_err(|_| format!("{kind:?} isnt a type"))?,
+        code: code.parse().map_err(|_| format!("{code:?} isnt a code"))?,
+        value: value.parse().map_err(|_| format!("{value:?} isnt a value"))?,
+    }))
+}
+
+fn parse(path: &str, report: &mut Report) -> Result<Recording, String> {
+    let file = std::fs::File::open(path).map_err(|err| format!("failed to open {path}: {err}"))?;
+    let mut recording = Recording { name: None, ranges: HashMap::new(), inputs: Vec::new() };
+    let mut abs = None; // the evtest axis whose Min/Max lines are next
+
+    for (i, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| format!("failed to read {path}: {err}"))?;
+        let line = line.split('#').next().unwrap_or_default().trim();
+
+        let input = if let Some(rest) = line.strip_prefix("E:") {
+            evemu(rest)
+        } else if let Some(rest) = line.strip_prefix("Event: time ") {
+            match evtest(rest) {
+                Ok(Some(input)) => Ok(input),
+                Ok(None) => continue,
+                Err(err) => Err(err),
+            }
+        } else {
+            if let Some(name) = line.strip_prefix("N:").or_else(|| line.strip_prefix("Input device name:")) {
+                recording.name = Some(name.trim().trim_matches('"').to_string());
+            } else if let Some(rest) = line.strip_prefix("A:") {
+                // evemu: A: <code hex> <min> <max> <fuzz> <flat> [<res>]
+                let parts: Vec<&str> = rest.split_whitespace().collect();
+                if let [code, min, max, ..] = parts[..] {
+                    if let (Ok(code), Ok(min), Ok(max)) = (hex(code), min.parse(), max.parse()) {
+                        recording.ranges.insert(code, Range { min, max });
+                    }
+                }
+            } else if line.starts_with("Event type") {
+                abs = None;
+            } else if let Some(code) = numbered(line, "Event code") {
+                // only axes have Min and Max under them, so keys never get a range
+                abs = code.parse::<u16>().ok();
+            } else if let (Some(code), Some(min)) = (abs, numbered(line, "Min")) {
+                let range = recording.ranges.entry(code).or_insert(Range::default_for(code));
+                range.min = min.parse().unwrap_or(range.min);
+            } else if let (Some(code), Some(max)) = (abs, numbered(line, "Max")) {
+                let range = recording.ranges.entry(code).or_insert(Range::default_for(code));
+                range.max = max.parse().unwrap_or(range.max);
+            }
+            continue;
+        };
+
+        match input {
+            Ok(input) => recording.inputs.push((i + 1, input)),
+            Err(err) => report.malformed.add(i + 1, err),
+        }
+    }
+
+    Ok(recording)
+}
+
+// gilrs doesnt let codes be made, but it does let them be deserialized,
+// and on linux a code is the evdev type and code, so this only works there
+fn code(kind: u16, code: u16) -> Result<gilrs::ev::Code, String> {
+    serde_json::from_value(serde_json::json!({ "kind": kind, "code": code }))
+        .map_err(|_| format!("evdev codes cant be made on {}, import recordings on linux", std::env::consts::OS))
+}
+
+/// Turns inputs into what gilrs would have sent for them, with its default linux mapping.
+struct Mapper {
+    ranges: HashMap<u16, Range>,
+    hats: HashMap<u16, i32>, // the last value of each hat, to know which dpad button to let go
+}
+
+impl Mapper {
+    fn range(&self, code: u16) -> Range {
+        self.ranges.get(&code).copied().unwrap_or(Range::default_for(code))
+    }
+
+    fn map(&mut self, input: &Input) -> Result<Vec<EventType>, String> {
+        let evcode = code(input.kind, input.code)?;
+
+        Ok(match input.kind {
+            EV_KEY => match BUTTONS.iter().find(|(code, _)| *code == input.code) {
+                Some((_, button)) => match input.value {
+                    0 => vec![EventType::ButtonReleased(*button, evcode), EventType::ButtonChanged(*button, 0.0, evcode)],
+                    1 => vec![EventType::ButtonPressed(*button, evcode), EventType::ButtonChanged(*button, 1.0, evcode)],
+                    _ => vec![EventType::ButtonRepeated(*button, evcode)],
+                },
+                None => vec![],
+            },
+            EV_ABS => {
+                let range = self.range(input.code);
+                match input.code {
+                    ABS_X => vec![EventType::AxisChanged(Axis::LeftStickX, range.signed(input.value), evcode)],
+                    // evdev has down as positive, gilrs has up
+                    ABS_Y => vec![EventType::AxisChanged(Axis::LeftStickY, -range.signed(input.value), evcode)],
+                    ABS_RX => vec![EventType::AxisChanged(Axis::RightStickX, range.signed(input.value), evcode)],
+                    ABS_RY => vec![EventType::AxisChanged(Axis::RightStickY, -range.signed(input.value), evcode)],
+                    // analog triggers come out of the sdl mappings as buttons
+                    ABS_Z => vec![EventType::ButtonChanged(Button::LeftTrigger2, range.unit(input.value), evcode)],
+                    ABS_RZ => vec![EventType::ButtonChanged(Button::RightTrigger2, range.unit(input.value), evcode)],
+                    ABS_HAT0X | ABS_HAT0Y => {
+                        let (less, more) = if input.code == ABS_HAT0X { (Button::DPadLeft, Button::DPadRight) } else { (Button::DPadUp, Button::DPadDown) };
+                        let last = self.hats.insert(input.code, input.value.signum()).unwrap_or(0);
+                        let mut events = vec![];
+                        let button = |value: i32| if value < 0 { less } else { more };
+                        if last != 0 && last != input.value.signum() {
+                            events.push(EventType::ButtonReleased(button(last), evcode));
+                            events.push(EventType::ButtonChanged(button(last), 0.0, evcode));
+                        }
+                        if input.value != 0 && last != input.value.signum() {
+                            events.push(EventType::ButtonPressed(button(input.value), evcode));
+                            events.push(EventType::ButtonChanged(button(input.value), 1.0, evcode));
+                        }
+                        events
+                    }
+                    _ => vec![],
+                }
+            }
+            _ => vec![],
+        })
+    }
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct Report {
+    imported: usize,
+    unmapped: usize, // inputs gilrs has no button or axis for, like EV_MSC or ABS_MISC
+    #[serde(flatten)]
+    malformed: Malformed,
+}
+
+/// Adds an `evtest` or `evemu-record` dump to `store` as `app`, under the pad name the dump has.
+/// evemu times start at 0, those are placed from `start` (ms since the unix epoch),
+/// or so the recording ends when the file was last written if not given.
+pub fn import(store: &dyn EventStore, path: &str, app: &str, start: Option<u128>) -> Result<Report, String> {
+    let mut report = Report::default();
+    let recording = parse(path, &mut report)?;
+
+    // evtest prints the time since the epoch, anything before 2001 can only be relative
+    let relative = recording.inputs.first().is_some_and(|(_, input)| input.time < 1_000_000_000_000_000);
+    let offset = match (relative, start) {
+        (false, _) => 0,
+        (true, Some(start)) => start * 1000,
+        (true, None) => {
+            let modified = std::fs::metadata(path)
+                .and_then(|meta| meta.modified())
+                .map_err(|err| format!("failed to read when {path} was written: {err}"))?;
+            let end = modified.duration_since(std::time::UNIX_EPOCH).map_err(|err| err.to_string())?.as_micros();
+            end.saturating_sub(recording.inputs.last().map_or(0, |(_, input)| input.time))
+        }
+    };
+
+    let pad = recording.name.unwrap_or_else(|| "?".to_string());
+    let mut mapper = Mapper { ranges: recording.ranges, hats: HashMap::new() };
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+    for (line, input) in recording.inputs.iter().filter(|(_, input)| input.kind != EV_SYN) {
+        // a wrong --start or a dump from a pad with a broken clock, either way nothing to keep
+        let at = (offset + input.time) / 1000;
+        if let Err(bad) = crate::integrity::check_at(at) {
+            report.malformed.add(*line, bad.to_string());
+            continue;
+        }
+
+        let events = mapper.map(input)?;
+        if events.is_empty() {
+            report.unmapped += 1;
+        }
+
+        batch.extend(events.into_iter().map(|event| Event { at, pad: pad.clone(), app: app.to_string(), event }));
+
+        if batch.len() >= BATCH_SIZE {
+            store.append(&batch)?;
+            report.imported += batch.len();
+            batch.clear();
+        }
+    }
+
+    store.append(&batch)?;
+    report.imported += batch.len();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::store::tests::Scratch;
+
+    const AT: u128 = 1_700_002_800_000;
+
+    fn counts(report: &Report) -> (u64, u64) {
+        let report = serde_json::to_value(report).unwrap();
+        (report["imported"].as_u64().unwrap(), report["malformed"].as_u64().unwrap())
+    }
+
+    #[test]
+    fn micros_dont_go_through_a_float() {
+        assert_eq!(micros("1.5"), Ok(1_500_000));
+        assert_eq!(micros("12"), Ok(12_000_000));
+        assert_eq!(micros("1700000000.123456"), Ok(1_700_000_000_123_456));
+        assert!(micros("1.é5").is_err());
+        assert!(micros("x.5").is_err());
+    }
+
+    #[test]
+    fn reads_evemu_lines() {
+        let input = evemu(" 0.000100 0003 0000 -1234").unwrap();
+        assert_eq!((input.time, input.kind, input.code, input.value), (100, EV_ABS, ABS_X, -1234));
+
+        assert!(evemu("0.000100 0003 0000").is_err());
+        assert!(evemu("0.000100 0003 zz 1").is_err());
+    }
+
+    #[test]
+    fn reads_evtest_lines() {
+        let input = evtest("1700000000.000001, type 3 (EV_ABS), code 0 (ABS_X), value -1234").unwrap().unwrap();
+        assert_eq!((input.time, input.kind, input.code, input.value), (1_700_000_000_000_001, EV_ABS, ABS_X, -1234));
+
+        assert!(evtest("1700000000.000001, -------------- SYN_REPORT ------------").unwrap().is_none());
+        assert!(evtest("1700000000.000001, type 3 (EV_ABS), code 0 (ABS_X)").is_err());
+        assert!(evtest("1700000000.000001, type 3 (EV_ABS), code 0 (ABS_X), level 2").is_err());
+    }
+
+    // code() needs the evdev layout
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn hats_press_and_release_the_dpad() {
+        let mut mapper = Mapper { ranges: HashMap::new(), hats: HashMap::new() };
+        let mut hat = |value| {
+            let events = mapper.map(&Input { time: 0, kind: EV_ABS, code: ABS_HAT0X, value }).unwrap();
+            events
+                .into_iter()
+                .filter_map(|event| match event {
+                    EventType::ButtonPressed(button, _) => Some((button, true)),
+                    EventType::ButtonReleased(button, _) => Some((button, false)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(hat(1), vec![(Button::DPadRight, true)]);
+        assert_eq!(hat(1), vec![]);
+        assert_eq!(hat(-1), vec![(Button::DPadRight, false), (Button::DPadLeft, true)]);
+        assert_eq!(hat(0), vec![(Button::DPadLeft, false)]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn imports_only_times_a_pad_could_have_sent() {
+        let scratch = Scratch::new("evdev");
+        let path = scratch.path("pad.evemu");
+        let dump = "N: Some Pad\nE: 0.000000 0001 0130 1\nE: 0.000000 0000 0000 0\nE: 0.001000 0001 0130 0\nE: 0.002000 0001 0130 q\n";
+        std::fs::write(&path, dump).unwrap();
+
+        let store = MemoryStore::default();
+        let report = import(&store, &path, "game", Some(AT)).unwrap();
+        assert_eq!(counts(&report), (4, 1));
+
+        let mut pads = Vec::new();
+        store.scan(0, u128::MAX, &mut |event| {
+            pads.push((event.at, event.pad));
+            true
+        }).unwrap();
+        assert_eq!(pads.len(), 4);
+        assert!(pads.iter().all(|(at, pad)| (AT..=AT + 1).contains(at) && pad == "Some Pad"));
+
+        // a start from before there were pads puts every input out of range
+        let report = import(&store, &path, "game", Some(5)).unwrap();
+        assert_eq!(counts(&report), (0, 4));
+    }
+}
@@ -0,0 +1,319 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float32Builder, StringBuilder, TimestampMillisecondBuilder, UInt32Builder};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::Deserialize;
+
+use crate::cli;
+use crate::jsonl::Line;
+use crate::store::{kind_name, Event, EventStore, KINDS};
+use crate::{AppStats, Application, Point};
+
+// csv columns, these are what scripts read so only ever add to the end
+//
+// applications: one row per app, most presses first
+//   app         the app the events are grouped under, its exe name or normalized window title, after the aliases
+//   controller  the controller (nickname or name) the first count for the app came from
+//   presses     every event, not only button presses, like the applications page
+//   combos      always 0 for now
+const APPLICATIONS_COLUMNS: &[&str] = &["app", "controller", "presses", "combos"];
+//
+// graph: one row per point, oldest first
+//   start   ms since the unix epoch the point starts at
+//   label   what the graph shows under it, utc
+//   events  every event in the point
+const GRAPH_COLUMNS: &[&str] = &["start", "label", "events"];
+//
+// app_stats: the buttons by name, then the axes by name and bucket
+//   kind    button or axis
+//   name    the gilrs name, like South or LeftStickX
+//   bucket  axis only, floor(value / width)
+//   from    axis only, the lowest value in the bucket
+//   to      axis only, the value the bucket stops before
+//   count   presses for a button, changes into the bucket for an axis
+const APP_STATS_COLUMNS: &[&str] = &["kind", "name", "bucket", "from", "to", "count"];
+
+/// Which events an export takes, everything left out matches all.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct Filter {
+    pub start: Option<u128>, // ms since the unix epoch, inclusive
+    pub end: Option<u128>,   // exclusive
+    pub app: Option<String>,
+    pub pad: Option<String>,
+    pub kinds: Vec<String>, // kind names like ButtonPressed, empty is every kind
+}
+
+impl Filter {
+    /// The filter from `--from`, `--to`, `--app`, `--pad` and `--kind` (comma separated).
+    pub fn from_args() -> Result<Filter, String> {
+        let filter = Filter {
+            start: cli::value("--from").map(|time| cli::parse_time(&time)).transpose()?,
+            end: cli::value("--to").map(|time| cli::parse_time(&time)).transpose()?,
+            app: cli::value("--app"),
+            pad: cli::value("--pad"),
+            kinds: cli::value("--kind")
+                .map(|kinds| kinds.split(',').map(|kind| kind.trim().to_string()).collect())
+                .unwrap_or_default(),
+        };
+
+        filter.check()?;
+        Ok(filter)
+    }
+
+    /// Catches a misspelled kind, which would otherwise quietly export nothing.
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(kind) = self.kinds.iter().find(|kind| !KINDS.contains(&kind.as_str())) {
+            return Err(format!("{kind} isnt an event type, it has to be one of {}", KINDS.join(", ")));
+        }
+
+        Ok(())
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        self.pad.iter().all(|pad| *pad == event.pad)
+            && (self.kinds.is_empty() || self.kinds.iter().any(|kind| kind == kind_name(&event.event)))
+    }
+
+    /// Calls `f` on every event that matches, newest first, until it returns false.
+    pub fn scan(&self, store: &dyn EventStore, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String> {
+        self.check()?;
+
+        let start = self.start.unwrap_or(0);
+        let end = self.end.unwrap_or(u128::MAX);
+        let mut matching = |event: Event| !self.matches(&event) || f(event);
+
+        // the app index means only that app is read at all
+        match &self.app {
+            Some(app) => store.scan_app(app, start, end, &mut matching),
+            None => store.scan(start, end, &mut matching),
+        }
+    }
+}
+
+/// Writes every matching event to `path` as jsonl, newest first, one at a time as they are read.
+/// Returns how many were written.
+pub fn jsonl(store: &dyn EventStore, filter: &Filter, path: &str) -> Result<usize, String> {
+    let file = std::fs::File::create(path).map_err(|err| format!("failed to create {path}: {err}"))?;
+    let mut out = std::io::BufWriter::new(file);
+
+    let mut written = 0;
+    let mut failed = None;
+    filter.scan(store, &mut |event| {
+        let line = Line::from(event);
+        let wrote = serde_json::to_writer(&mut out, &line).map_err(|err| err.to_string())
+            .and_then(|_| out.write_all(b"\n").map_err(|err| err.to_string()));
+
+        match wrote {
+            Ok(()) => {
+                written += 1;
+                true
+            }
+            Err(err) => {
+                failed = Some(err);
+                false
+            }
+        }
+    })?;
+
+    if let Some(err) = failed {
+        return Err(format!("failed to write {path}: {err}"));
+    }
+
+    out.flush().map_err(|err| format!("failed to write {path}: {err}"))?;
+    Ok(written)
+}
+
+// rows per parquet row group, the most that is ever held in memory
+const PARQUET_ROWS: usize = 64 * 1024;
+
+// one builder per parquet column, emptied every PARQUET_ROWS
+//   timestamp   utc ms
+//   app, pad    as captured
+//   kind        ButtonPressed, AxisChanged, ...
+//   name        the gilrs button or axis, null for connection events
+//   value       for ButtonChanged and AxisChanged, null for the rest
+//   code_page   the raw code the os gave, its high and low halves
+//   code_usage
+struct Columns {
+    schema: Arc<Schema>,
+    timestamp: TimestampMillisecondBuilder,
+    app: StringBuilder,
+    pad: StringBuilder,
+    kind: StringBuilder,
+    name: StringBuilder,
+    value: Float32Builder,
+    code_page: UInt32Builder,
+    code_usage: UInt32Builder,
+    rows: usize,
+}
+
+impl Columns {
+    fn new() -> Columns {
+        let schema = Schema::new(vec![
+            Field::new("timestamp", DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())), false),
+            Field::new("app", DataType::Utf8, false),
+            Field::new("pad", DataType::Utf8, false),
+            Field::new("kind", DataType::Utf8, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("value", DataType::Float32, true),
+            Field::new("code_page", DataType::UInt32, true),
+            Field::new("code_usage", DataType::UInt32, true),
+        ]);
+
+        Columns {
+            schema: Arc::new(schema),
+            timestamp: TimestampMillisecondBuilder::new().with_timezone("UTC"),
+            app: StringBuilder::new(),
+            pad: StringBuilder::new(),
+            kind: StringBuilder::new(),
+            name: StringBuilder::new(),
+            value: Float32Builder::new(),
+            code_page: UInt32Builder::new(),
+            code_usage: UInt32Builder::new(),
+            rows: 0,
+        }
+    }
+
+    fn push(&mut self, event: &Event) {
+        use gilrs::EventType::*;
+
+        let (name, value, code) = match event.event {
+            ButtonPressed(button, code) | ButtonRepeated(button, code) | ButtonReleased(button, code) => {
+                (Some(format!("{button:?}")), None, Some(code))
+            }
+            ButtonChanged(button, value, code) => (Some(format!("{button:?}")), Some(value), Some(code)),
+            AxisChanged(axis, value, code) => (Some(format!("{axis:?}")), Some(value), Some(code)),
+            Connected | Disconnected | Dropped => (None, None, None),
+        };
+
+        // every platform packs its two parts of the code into the halves of this
+        let code = code.map(|code| code.into_u32());
+
+        self.timestamp.append_value(event.at.min(i64::MAX as u128) as i64);
+        self.app.append_value(&event.app);
+        self.pad.append_value(&event.pad);
+        self.kind.append_value(kind_name(&event.event));
+        self.name.append_option(name);
+        self.value.append_option(value);
+        self.code_page.append_option(code.map(|code| code >> 16));
+        self.code_usage.append_option(code.map(|code| code & 0xffff));
+        self.rows += 1;
+    }
+
+    // empties the builders into a batch
+    fn finish(&mut self) -> Result<RecordBatch, String> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.timestamp.finish()),
+            Arc::new(self.app.finish()),
+            Arc::new(self.pad.finish()),
+            Arc::new(self.kind.finish()),
+            Arc::new(self.name.finish()),
+            Arc::new(self.value.finish()),
+            Arc::new(self.code_page.finish()),
+            Arc::new(self.code_usage.finish()),
+        ];
+
+        self.rows = 0;
+        RecordBatch::try_new(Arc::clone(&self.schema), columns).map_err(|err| err.to_string())
+    }
+}
+
+/// Writes every matching event to `path` as parquet, newest first.
+/// Rows go out a row group at a time, so memory stays the same however much there is.
+/// Returns how many were written.
+pub fn parquet(store: &dyn EventStore, filter: &Filter, path: &str) -> Result<usize, String> {
+    let file = std::fs::File::create(path).map_err(|err| format!("failed to create {path}: {err}"))?;
+
+    let mut columns = Columns::new();
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_max_row_group_size(PARQUET_ROWS)
+        .build();
+    let mut out = ArrowWriter::try_new(file, Arc::clone(&columns.schema), Some(props)).map_err(|err| err.to_string())?;
+
+    let mut written = 0;
+    let mut failed = None;
+    filter.scan(store, &mut |event| {
+        columns.push(&event);
+        written += 1;
+        if columns.rows < PARQUET_ROWS {
+            return true;
+        }
+
+        match columns.finish().and_then(|batch| out.write(&batch).map_err(|err| err.to_string())) {
+            Ok(()) => true,
+            Err(err) => {
+                failed = Some(err);
+                false
+            }
+        }
+    })?;
+
+    if let Some(err) = failed {
+        return Err(format!("failed to write {path}: {err}"));
+    }
+
+    let batch = columns.finish()?;
+    out.write(&batch).map_err(|err| format!("failed to write {path}: {err}"))?;
+    out.close().map_err(|err| format!("failed to write {path}: {err}"))?;
+    Ok(written)
+}
+
+fn write_csv(path: &str, columns: &[&str], rows: Vec<Vec<String>>) -> Result<(), String> {
+    let mut out = csv::Writer::from_path(path).map_err(|err| format!("failed to create {path}: {err}"))?;
+
+    // the header goes in even with no rows, so the file always reads the same way
+    out.write_record(columns).map_err(|err| err.to_string())?;
+    for row in rows {
+        out.write_record(&row).map_err(|err| err.to_string())?;
+    }
+
+    out.flush().map_err(|err| format!("failed to write {path}: {err}"))
+}
+
+pub fn applications_csv(apps: &[Application], path: &str) -> Result<(), String> {
+    let mut apps: Vec<&Application> = apps.iter().collect();
+    apps.sort_by(|a, b| b.presses.cmp(&a.presses).then_with(|| a.name.cmp(&b.name)));
+
+    let rows = apps.into_iter()
+        .map(|app| vec![app.name.clone(), app.controller.clone(), app.presses.to_string(), app.combos.to_string()])
+        .collect();
+
+    write_csv(path, APPLICATIONS_COLUMNS, rows)
+}
+
+pub fn graph_csv(points: &[Point], path: &str) -> Result<(), String> {
+    let rows = points.iter()
+        .map(|point| vec![point.at.to_string(), point.label.trim().to_string(), point.data.to_string()])
+        .collect();
+
+    write_csv(path, GRAPH_COLUMNS, rows)
+}
+
+pub fn app_stats_csv(stats: &AppStats, path: &str) -> Result<(), String> {
+    let mut rows = Vec::new();
+
+    let mut buttons: Vec<_> = stats.presses.iter().map(|button| (format!("{:?}", button.name), button.presses)).collect();
+    buttons.sort();
+    for (name, presses) in buttons {
+        rows.push(vec!["button".to_string(), name, String::new(), String::new(), String::new(), presses.to_string()]);
+    }
+
+    let mut axes: Vec<_> = stats.axes.iter()
+        .flat_map(|axis| axis.pos_buckets.iter().map(move |(bucket, n)| (format!("{:?}", axis.name), *bucket, axis.h, *n)))
+        .collect();
+    axes.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+    for (name, bucket, h, n) in axes {
+        // fixed places, so float noise doesnt show up as a change
+        let from = bucket as f32 * h;
+        rows.push(vec!["axis".to_string(), name, bucket.to_string(), format!("{from:.3}"), format!("{:.3}", from + h), n.to_string()]);
+    }
+
+    write_csv(path, APP_STATS_COLUMNS, rows)
+}
@@ -0,0 +1,207 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::identity::Identity;
+use crate::UserSettings;
+
+// how often /proc is looked through, a game takes longer than this to start anyway
+const SCAN_EVERY: Duration = Duration::from_secs(2);
+
+// what wine and proton run next to every game, none of them are the game
+const WINE_HELPERS: &[&str] = &[
+    "conhost.exe", "explorer.exe", "plugplay.exe", "rpcss.exe", "services.exe", "start.exe", "steam.exe",
+    "svchost.exe", "tabtip.exe", "wineboot.exe", "winedevice.exe", "winemenubuilder.exe", "winedbg.exe",
+];
+
+/// Where FOCUSED_APP comes from.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Attribution {
+    pub source: String,     // window, process, or auto, which is window unless on wayland or without a display
+    pub games: Vec<String>, // binaries that count as games, by file name like "factorio", for the process source
+}
+
+impl Default for Attribution {
+    fn default() -> Self {
+        Attribution { source: "auto".to_string(), games: Vec::new() }
+    }
+}
+
+impl Attribution {
+    /// If the running games should be used instead of the focused window.
+    /// Only linux has a /proc, everywhere else this is always the window.
+    pub fn uses_processes(&self) -> bool {
+        if !cfg!(target_os = "linux") {
+            return false;
+        }
+
+        match self.source.as_str() {
+            "process" => true,
+            "window" => false,
+            // xwayland only sees its own windows, so on wayland the title is wrong more than its right
+            _ => std::env::var("XDG_SESSION_TYPE").is_ok_and(|session| session == "wayland") || std::env::var_os("DISPLAY").is_none(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Game {
+    pub name: String,
+    pub pid: u32,
+    started: u64, // clock ticks after boot, the newest game wins
+}
+
+// the file name of the program from /proc/<pid>/cmdline, for wine that is the windows exe, not wine itself
+fn program(cmdline: &[u8]) -> Option<String> {
+    let argv0 = String::from_utf8_lossy(cmdline.split(|b| *b == 0).next()?).into_owned();
+    argv0.rsplit(['/', '\\']).next().map(str::to_string).filter(|name| !name.is_empty())
+}
+
+// from /proc/<pid>/stat
+fn started(stat: &str) -> Option<u64> {
+    // the name can have spaces and brackets in it, so count from the last )
+    stat.rsplit_once(')')?.1.split_whitespace().nth(19)?.parse().ok()
+}
+
+// from /proc/<pid>/environ, SteamAppId is set for the game and everything it starts, proton included
+fn steam_app(environ: &[u8]) -> Option<String> {
+    environ.split(|b| *b == 0)
+        .find_map(|var| var.strip_prefix(b"SteamAppId="))
+        .map(|id| String::from_utf8_lossy(id).into_owned())
+        .filter(|id| !id.is_empty() && id != "0")
+}
+
+// the name steam shows, from the library the game was installed to
+fn steam_name(id: &str) -> Option<String> {
+    let home = std::env::var("HOME").ok()?;
+    let manifest = ["/.steam/steam/steamapps", "/.local/share/Steam/steamapps"].iter()
+        .find_map(|dir| std::fs::read_to_string(format!("{home}{dir}/appmanifest_{id}.acf")).ok())?;
+
+    // "name"		"Celeste"
+    manifest.lines()
+        .find_map(|line| line.trim().strip_prefix("\"name\""))
+        .map(|name| name.trim().trim_matches('"').to_string())
+}
+
+// if a process is a game, how sure that is, lower is surer, and what to call it
+fn rank(games: &[String], program: &str, steam_app: Option<&str>) -> Option<(u8, String)> {
+    if games.iter().any(|game| game.eq_ignore_ascii_case(program)) {
+        Some((0, program.to_string()))
+    } else if let Some(id) = steam_app {
+        Some((1, steam_name(id).unwrap_or_else(|| format!("steam {id}"))))
+    } else if program.to_ascii_lowercase().ends_with(".exe") && !WINE_HELPERS.contains(&program.to_ascii_lowercase().as_str()) {
+        // lowercasing only touches ascii, so the last 4 bytes are the extension in whatever case it was
+        Some((2, program[..program.len() - 4].to_string()))
+    } else {
+        None
+    }
+}
+
+// the surest, then the one that started last
+fn best(found: Vec<(u8, Game)>) -> Option<Game> {
+    found.into_iter()
+        .min_by(|(a, a_game), (b, b_game)| a.cmp(b).then(b_game.started.cmp(&a_game.started)))
+        .map(|(_, game)| game)
+}
+
+/// The game that started last, going by the user's list, then SteamAppId, then anything running under wine.
+pub fn find(games: &[String]) -> Option<Game> {
+    let mut found: Vec<(u8, Game)> = Vec::new();
+
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|pid| pid.parse::<u32>().ok()) else {
+            continue;
+        };
+        let path = entry.path();
+        // processes come and go while this runs, one gone missing is skipped
+        let program = std::fs::read(path.join("cmdline")).ok().and_then(|cmdline| program(&cmdline));
+        let started = std::fs::read_to_string(path.join("stat")).ok().and_then(|stat| started(&stat));
+        let (Some(program), Some(started)) = (program, started) else {
+            continue;
+        };
+
+        let steam = std::fs::read(path.join("environ")).ok().and_then(|environ| steam_app(&environ));
+        if let Some((rank, name)) = rank(games, &program, steam.as_deref()) {
+            found.push((rank, Game { name, pid, started }));
+        }
+    }
+
+    best(found)
+}
+
+/// Keeps `focused` on the running game while the settings say to use processes.
+/// "?" while there is no game. Switching back puts `window` in, the last window focused, or "?" without a display.
+pub fn spawn(settings: Arc<Mutex<UserSettings>>, focused: &'static Mutex<Identity>, window: &'static Mutex<Option<Identity>>) {
+    std::thread::spawn(move || {
+        let mut last: Option<Option<Game>> = None; // None when the window was in charge
+        loop {
+            let attribution = settings.lock().unwrap().attribution.clone();
+            if attribution.uses_processes() {
+                let game = find(&attribution.games);
+                if last.as_ref() != Some(&game) {
+                    log::debug!("Game changed to: {game:?}");
+                    // no exe, the name is already what it should be grouped under and proton games are all wine
+                    *focused.lock().unwrap() = match &game {
+                        Some(game) => Identity { pid: Some(game.pid), ..Identity::titled(&game.name) },
+                        None => Identity::titled("?"),
+                    };
+                    last = Some(game);
+                }
+            } else if last.take().is_some() {
+                // the window only gets set on a focus change, which might not come for a while
+                let window = window.lock().unwrap();
+                *focused.lock().unwrap() = window.clone().unwrap_or_else(|| Identity::titled("?"));
+            }
+
+            std::thread::sleep(SCAN_EVERY);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_program_from_cmdline() {
+        assert_eq!(program(b"/usr/games/factorio\0--start-server\0save.zip\0"), Some("factorio".to_string()));
+        assert_eq!(program(b"C:\\Program Files\\Celeste\\Celeste.exe\0"), Some("Celeste.exe".to_string()));
+        // kernel threads have nothing
+        assert_eq!(program(b""), None);
+        assert_eq!(program(b"/usr/bin/\0"), None);
+    }
+
+    #[test]
+    fn reads_when_it_started_from_stat() {
+        let stat = "1234 (my (game)) S 1 1234 1234 0 -1 4194560 100 0 0 0 10 5 0 0 20 0 4 0 98765 1000 200 18446744073709551615";
+        assert_eq!(started(stat), Some(98765));
+        assert_eq!(started("1234 (game) S 1"), None);
+        assert_eq!(started("1234 game"), None);
+    }
+
+    #[test]
+    fn reads_the_steam_app_from_environ() {
+        assert_eq!(steam_app(b"HOME=/home/me\0SteamAppId=504230\0STEAM_COMPAT=1\0"), Some("504230".to_string()));
+        // steam sets 0 for things it starts that arent games
+        assert_eq!(steam_app(b"SteamAppId=0\0"), None);
+        assert_eq!(steam_app(b"SteamAppId=\0"), None);
+        assert_eq!(steam_app(b"HOME=/home/me\0"), None);
+    }
+
+    #[test]
+    fn ranks_the_users_games_then_steam_then_wine() {
+        let games = ["Factorio".to_string()];
+        assert_eq!(rank(&games, "factorio", Some("427520")), Some((0, "factorio".to_string())));
+        assert_eq!(rank(&games, "reaper", Some("999999999")), Some((1, "steam 999999999".to_string())));
+        assert_eq!(rank(&games, "Celeste.EXE", None), Some((2, "Celeste".to_string())));
+        assert_eq!(rank(&games, "wineboot.exe", None), None);
+        assert_eq!(rank(&games, "bash", None), None);
+
+        let game = |rank, name: &str, started| (rank, Game { name: name.to_string(), pid: 1, started });
+        assert_eq!(best(vec![game(2, "wine", 30), game(1, "steam", 10), game(1, "newer steam", 20)]).unwrap().name, "newer steam");
+        assert_eq!(best(vec![game(2, "wine", 30), game(0, "listed", 10)]).unwrap().name, "listed");
+        assert_eq!(best(vec![]), None);
+    }
+}
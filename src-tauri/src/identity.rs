@@ -0,0 +1,171 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+// how many raw titles an app remembers, the newest win
+const TITLES: usize = 32;
+
+// executables that run someone elses code, their name says nothing about what is running
+const RUNTIMES: &[&str] = &[
+    "ApplicationFrameHost", "dotnet", "electron", "java", "javaw", "mono", "node", "python", "python3", "pythonw",
+    "wine", "wine64", "wine-preloader", "wine64-preloader",
+];
+
+// executables plenty of different things ship as, unity builds are all Game.exe and every site is chrome
+const GENERIC: &[&str] = &[
+    "game", "main", "app", "launcher", "chrome", "firefox", "msedge", "opera", "brave", "safari",
+];
+
+// bits of a title that come and go while the same thing is running
+const STATUS: &[&str] = &["loading", "loading...", "paused", "menu", "main menu", "not responding"];
+
+/// What the focus tracker knows about whatever has focus.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct Identity {
+    pub title: String,       // as the window had it
+    pub exe: Option<String>, // full path
+    pub pid: Option<u32>,
+}
+
+impl Identity {
+    pub const fn unknown() -> Identity {
+        Identity { title: String::new(), exe: None, pid: None }
+    }
+
+    pub fn titled(title: &str) -> Identity {
+        Identity { title: title.to_string(), ..Identity::unknown() }
+    }
+
+    /// The name events are stored and grouped under.
+    /// The executable if there is one worth using, since it stays put while the title changes, or the normalized title.
+    /// Runtimes and generic names like Game.exe or a browser dont say what is running, those go by the title too.
+    pub fn app(&self) -> String {
+        self.exe.as_deref()
+            .and_then(|exe| Path::new(exe).file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .filter(|stem| !RUNTIMES.iter().chain(GENERIC).any(|name| name.eq_ignore_ascii_case(stem)))
+            .unwrap_or_else(|| normalize(&self.title))
+    }
+}
+
+// without the punctuation around it, "(60" is 60
+fn core(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric() && c != '.').to_ascii_lowercase()
+}
+
+fn numeric(word: &str) -> bool {
+    let word = core(word);
+    !word.is_empty() && word.chars().all(|c| c.is_ascii_digit() || c == '.')
+}
+
+// "FPS", "60fps"
+fn fps(word: &str) -> bool {
+    core(word).strip_suffix("fps").is_some_and(|n| n.chars().all(|c| c.is_ascii_digit() || c == '.'))
+}
+
+// "1.2.3", "v0.9"
+fn version(word: &str) -> bool {
+    let word = core(word);
+    let word = word.strip_prefix('v').unwrap_or(&word);
+    word.contains('.') && numeric(word)
+}
+
+/// A window title without what changes from moment to moment,
+/// fps counters ("60 FPS", "FPS: 60"), version numbers and status parts ("Skyrim - Loading").
+pub fn normalize(title: &str) -> String {
+    let words: Vec<&str> = title.split_whitespace().collect();
+    let mut keep = vec![true; words.len()];
+    for (i, word) in words.iter().enumerate() {
+        if fps(word) {
+            keep[i] = false;
+            // the number can be on either side
+            if i > 0 && numeric(words[i - 1]) {
+                keep[i - 1] = false;
+            }
+            if i + 1 < words.len() && numeric(words[i + 1]) {
+                keep[i + 1] = false;
+            }
+        } else if version(word) {
+            keep[i] = false;
+        }
+    }
+
+    let title = words.iter().zip(&keep).filter(|(_, keep)| **keep).map(|(word, _)| *word).collect::<Vec<_>>().join(" ");
+    let title = title.replace(" | ", " - ").replace(" — ", " - ");
+    let parts: Vec<&str> = title.split(" - ")
+        .map(str::trim)
+        .filter(|part| !part.is_empty() && !STATUS.contains(&part.to_lowercase().as_str()))
+        .collect();
+
+    // a title thats nothing but status is still better than nothing
+    let normalized = parts.join(" - ");
+    if normalized.is_empty() {
+        title.trim().to_string()
+    } else {
+        normalized
+    }
+}
+
+/// Everything seen for one app, kept in the dictionary next to its name.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Known {
+    pub exe: Option<String>,
+    pub pid: Option<u32>,    // the last one
+    pub titles: Vec<String>, // raw titles, oldest first
+}
+
+impl Known {
+    pub fn learn(&mut self, identity: &Identity) {
+        if identity.exe.is_some() {
+            self.exe.clone_from(&identity.exe);
+        }
+        if identity.pid.is_some() {
+            self.pid = identity.pid;
+        }
+
+        self.titles.retain(|title| *title != identity.title);
+        self.titles.push(identity.title.clone());
+        if self.titles.len() > TITLES {
+            self.titles.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_what_changes() {
+        for (title, normalized) in [
+            ("Skyrim - Loading", "Skyrim"),
+            ("Hades | Paused", "Hades"),
+            ("Celeste v1.4.0.0", "Celeste"),
+            ("Minecraft 1.20.1", "Minecraft"),
+            ("Quake 60 FPS", "Quake"),
+            ("Quake FPS: 144", "Quake"),
+            ("Quake (60fps)", "Quake"),
+            ("Doom 3", "Doom 3"),
+            ("Loading", "Loading"),
+            ("", ""),
+        ] {
+            assert_eq!(normalize(title), normalized, "{title:?}");
+        }
+    }
+
+    #[test]
+    fn names_apps_by_exe_unless_it_says_nothing() {
+        let identity = |exe: Option<&str>, title: &str| Identity { exe: exe.map(str::to_string), ..Identity::titled(title) };
+        for (exe, title, app) in [
+            (Some("/games/celeste/Celeste.exe"), "Celeste v1.4.0.0", "Celeste"),
+            (Some("/usr/bin/java"), "Minecraft 1.20.1", "Minecraft"),
+            (Some("/usr/bin/wine64-preloader"), "Hades - Paused", "Hades"),
+            (Some("/games/hollow/Game.exe"), "Hollow Knight", "Hollow Knight"),
+            (Some("/opt/google/chrome/chrome"), "Some Web Game - Google Chrome", "Some Web Game - Google Chrome"),
+            (Some("/usr/bin/FIREFOX"), "Skyrim - Loading", "Skyrim"),
+            (None, "Skyrim - Loading", "Skyrim"),
+        ] {
+            assert_eq!(identity(exe, title).app(), app, "{exe:?} {title:?}");
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use rocksdb::{WriteBatch, DB};
+
+use crate::store::{Skipped, BATCH_SIZE};
+use crate::{decode_key, integrity, Rock, KEY_LEN};
+
+// rocks by app, so app_stats only has to look at one app
+// key: [app id, at (big endian), nonce], the tail is the primary key without the version
+// value: the primary key
+pub const APP_INDEX_CF: &str = "by_app";
+
+// set once the index has been built from the raw events
+pub const BUILT_KEY: &[u8] = b"app_index";
+
+fn prefix(app: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(4 + KEY_LEN - 1);
+    key.extend_from_slice(&app.to_be_bytes());
+    key
+}
+
+fn encode(app: u32, pk: &[u8]) -> Vec<u8> {
+    let mut key = prefix(app);
+    key.extend_from_slice(&pk[1..]);
+    key
+}
+
+/// Indexes the rock, in the same batch as the rock so they cant drift.
+pub fn record(db: &DB, batch: &mut WriteBatch, pk: &[u8], rock: &Rock) {
+    let cf = db.cf_handle(APP_INDEX_CF).unwrap();
+    batch.put_cf(cf, encode(rock.app, pk), pk);
+}
+
+/// Drops the rock from the index, for when the rock itself is deleted.
+pub fn remove(db: &DB, batch: &mut WriteBatch, pk: &[u8], rock: &Rock) {
+    let cf = db.cf_handle(APP_INDEX_CF).unwrap();
+    batch.delete_cf(cf, encode(rock.app, pk));
+}
+
+/// Calls `f` with the primary key of every rock for `app` with `start <= at < end`,
+/// newest first, until it returns false.
+pub fn scan(db: &DB, app: u32, start: u128, end: u128, f: &mut dyn FnMut(&[u8]) -> bool) -> Result<(), String> {
+    let cf = db.cf_handle(APP_INDEX_CF).ok_or("missing app index column family")?;
+
+    let prefix = prefix(app);
+    let mut last = prefix.clone();
+    last.extend_from_slice(&end.to_be_bytes());
+
+    let mut skipped = Skipped::new("app index scan");
+    for row in db.iterator_cf(cf, rocksdb::IteratorMode::From(&last, rocksdb::Direction::Reverse)) {
+        let (key, pk) = row.map_err(|err| err.to_string())?;
+        if !key.starts_with(&prefix) {
+            break;
+        }
+
+        let Ok(at) = decode_key(&pk) else {
+            skipped.add(format!("app index points at a bad key {pk:?}"));
+            continue;
+        };
+
+        // the seek lands before every key at `end`, since those are longer than `last`, so this never happens
+        // it stays in case the key layout changes
+        if at >= end {
+            continue;
+        }
+
+        if at < start || !f(&pk) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Throws the index away and indexes every raw event again.
+pub fn rebuild(db: &DB) -> Result<usize, String> {
+    let cf = db.cf_handle(APP_INDEX_CF).ok_or("missing app index column family")?;
+    // past the longest key there is, so every app is in the range
+    db.delete_range_cf(cf, [0u8; 4].as_slice(), [0xffu8; 4 + KEY_LEN].as_slice()).map_err(|err| err.to_string())?;
+
+    let mut indexed = 0;
+    let mut skipped = Skipped::new("app index rebuild");
+    let mut batch = WriteBatch::default();
+    for row in db.iterator(rocksdb::IteratorMode::Start) {
+        let (key, value) = row.map_err(|err| err.to_string())?;
+        let rock = match integrity::check(&key, &value) {
+            Ok(rock) => rock,
+            Err(bad) => {
+                skipped.add(bad);
+                continue;
+            }
+        };
+
+        record(db, &mut batch, &key, &rock);
+        indexed += 1;
+
+        if batch.len() >= BATCH_SIZE {
+            db.write(std::mem::take(&mut batch)).map_err(|err| err.to_string())?;
+        }
+    }
+    db.write(batch).map_err(|err| err.to_string())?;
+
+    let meta = db.cf_handle(crate::migrate::META_CF).ok_or("missing meta column family")?;
+    db.put_cf(meta, BUILT_KEY, [1]).map_err(|err| err.to_string())?;
+
+    Ok(indexed)
+}
+
+/// Builds the index the first time a db without it is opened.
+pub fn ensure(db: &DB) -> Result<(), String> {
+    let meta = db.cf_handle(crate::migrate::META_CF).ok_or("missing meta column family")?;
+    if db.get_cf(meta, BUILT_KEY).map_err(|err| err.to_string())?.is_some() {
+        return Ok(());
+    }
+
+    let indexed = rebuild(db)?;
+    log::info!("indexed {indexed} rocks by app");
+    Ok(())
+}
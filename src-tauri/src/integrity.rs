@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocksdb::{WriteBatch, DB};
+use serde::{Deserialize, Serialize};
+
+use crate::store::{BATCH_SIZE, SKIPPED};
+use crate::{decode_key, Rock, DAY, DB_VERSION, KEY_LEN};
+
+// rows that failed the check, moved out of the way so nothing trips on them again
+// key: the row's original key
+// value: bincode Quarantined
+pub const QUARANTINE_CF: &str = "quarantine";
+
+// 2000-01-01, nothing older came from a controller
+const EARLIEST: u128 = 946_684_800_000;
+// clocks drift, but not by a day
+const SLACK: u128 = DAY;
+// a few keys to look up by hand, the kinds count the rest
+const EXAMPLES: usize = 10;
+
+/// Why a row is bad, `kind` is stable so reports can be grouped by it.
+#[derive(Debug)]
+pub struct Bad {
+    pub kind: &'static str,
+    pub detail: String,
+}
+
+impl std::fmt::Display for Bad {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind, self.detail)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Quarantined {
+    reason: String,
+    found: u128, // ms since the unix epoch
+    value: Vec<u8>,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct Report {
+    checked: usize,
+    bad: usize,
+    pub quarantined: usize, // moved this time
+    in_quarantine: usize,   // moved ever
+    skipped_by_readers: usize, // since startup
+    unmigrated: usize, // rows an older migration left behind, counted in bad but never quarantined
+    kinds: HashMap<&'static str, usize>,
+    examples: Vec<String>,
+    hint: Option<String>, // what to do about them
+}
+
+fn bad(kind: &'static str, detail: String) -> Bad {
+    Bad { kind, detail }
+}
+
+/// A time a controller could have sent something at.
+pub fn check_at(at: u128) -> Result<(), Bad> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis();
+    if !(EARLIEST..=now + SLACK).contains(&at) {
+        return Err(bad("timestamp", format!("{at} is not between {EARLIEST} and now")));
+    }
+
+    Ok(())
+}
+
+/// Everything a raw row has to be before it is handed out as a Rock.
+pub fn check(key: &[u8], value: &[u8]) -> Result<Rock, Bad> {
+    // first, an older layout can be any length and is the migrations to deal with
+    if let Some(version) = key.first().filter(|version| **version != DB_VERSION) {
+        return Err(bad("version", format!("v{version}, not v{DB_VERSION}")));
+    }
+
+    if key.len() != KEY_LEN {
+        return Err(bad("key length", format!("{} bytes, not {KEY_LEN}", key.len())));
+    }
+
+    let at = decode_key(key).map_err(|err| bad("key", err))?;
+    check_at(at)?;
+
+    let rock: Rock = bincode::deserialize(value).map_err(|err| bad("decode", err.to_string()))?;
+    if rock.at != at {
+        return Err(bad("timestamp", format!("the rock says {} but its key says {at}", rock.at)));
+    }
+
+    Ok(rock)
+}
+
+/// Moves the raw row at `key` into QUARANTINE_CF as part of `batch`.
+pub fn quarantine(db: &DB, batch: &mut WriteBatch, key: &[u8], value: Vec<u8>, reason: String) -> Result<(), String> {
+    let cf = db.cf_handle(QUARANTINE_CF).ok_or("missing quarantine column family")?;
+    let found = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis();
+
+    batch.put_cf(cf, key, bincode::serialize(&Quarantined { reason, found, value }).unwrap());
+    batch.delete(key);
+    Ok(())
+}
+
+/// Checks every raw row, and with `quarantine` moves the bad ones to QUARANTINE_CF.
+/// Rows from an older version are left where they are, the migration brings them up or quarantines them itself.
+/// Anything derived from a quarantined row is still there, the caller should rebuild after.
+pub fn scan(db: &DB, quarantine: bool) -> Result<Report, String> {
+    let cf = db.cf_handle(QUARANTINE_CF).ok_or("missing quarantine column family")?;
+
+    let mut report = Report::default();
+    let mut batch = WriteBatch::default();
+    for row in db.iterator(rocksdb::IteratorMode::Start) {
+        let (key, value) = row.map_err(|err| err.to_string())?;
+        report.checked += 1;
+
+        let Err(bad) = check(&key, &value) else {
+            continue;
+        };
+
+        report.bad += 1;
+        *report.kinds.entry(bad.kind).or_default() += 1;
+        if report.examples.len() < EXAMPLES {
+            let hex: String = key.iter().map(|b| format!("{b:02x}")).collect();
+            report.examples.push(format!("{hex}: {bad}"));
+        }
+
+        if bad.kind == "version" {
+            report.unmigrated += 1;
+            continue;
+        }
+
+        if quarantine {
+            self::quarantine(db, &mut batch, &key, value.into_vec(), bad.to_string())?;
+            report.quarantined += 1;
+
+            if report.quarantined % BATCH_SIZE == 0 {
+                db.write(std::mem::take(&mut batch)).map_err(|err| err.to_string())?;
+            }
+        }
+    }
+
+    db.write(batch).map_err(|err| err.to_string())?;
+
+    if report.unmigrated > 0 {
+        report.hint = Some(format!("{} rows are from an older db version, start coca once with --migrate-again to migrate them", report.unmigrated));
+    }
+
+    report.in_quarantine = db.iterator_cf(cf, rocksdb::IteratorMode::Start).count();
+    report.skipped_by_readers = SKIPPED.load(Ordering::Relaxed);
+    Ok(report)
+}
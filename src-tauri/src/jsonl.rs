@@ -0,0 +1,182 @@
+use std::io::BufRead;
+
+use serde::{Deserialize, Serialize};
+
+use crate::integrity;
+use crate::store::{Event, EventStore};
+
+// written this many at a time, like the writer thread
+pub const BATCH_SIZE: usize = 1024;
+// a file from another os can have every line wrong, the count says how many
+const ERRORS: usize = 50;
+
+/// One event per line, how rocks.jsonl was written and how exports are.
+/// Older files have no app, and some no pad either, whoever reads them fills those in.
+#[derive(Serialize, Deserialize)]
+pub struct Line {
+    pub at: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pad: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app: Option<String>,
+    pub event: gilrs::EventType,
+}
+
+impl Line {
+    /// The event, with `app` and `pad` standing in for whatever the line left out.
+    pub fn into_event(self, app: &str, pad: &str) -> Event {
+        Event {
+            at: self.at,
+            pad: self.pad.unwrap_or_else(|| pad.to_string()),
+            app: self.app.unwrap_or_else(|| app.to_string()),
+            event: self.event,
+        }
+    }
+
+    /// Catches what serde lets through but a controller could never have sent.
+    pub fn check(&self) -> Result<(), String> {
+        integrity::check_at(self.at).map_err(|bad| bad.to_string())?;
+
+        match self.event {
+            gilrs::EventType::AxisChanged(_, value, _) if !(-1.0..=1.0).contains(&value) => {
+                Err(format!("axis value {value} is outside -1 to 1"))
+            }
+            gilrs::EventType::ButtonChanged(_, value, _) if !(0.0..=1.0).contains(&value) => {
+                Err(format!("button value {value} is outside 0 to 1"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl From<Event> for Line {
+    fn from(event: Event) -> Line {
+        Line { at: event.at, pad: Some(event.pad), app: Some(event.app), event: event.event }
+    }
+}
+
+/// Calls `f` with the line number (from 1) and every line parsed, blank lines are skipped.
+/// A line that wont parse is handed over as an error, so the caller decides if that stops it.
+pub fn read(path: &str, f: &mut dyn FnMut(usize, Result<Line, String>) -> Result<(), String>) -> Result<(), String> {
+    let file = std::fs::File::open(path).map_err(|err| format!("failed to open {path}: {err}"))?;
+
+    for (i, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| format!("failed to read {path}: {err}"))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        f(i + 1, serde_json::from_str(&line).map_err(|err| err.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Lines of an import that wont parse, every import reports them the same way.
+#[derive(Serialize, Default, Debug)]
+pub struct Malformed {
+    malformed: usize,
+    errors: Vec<String>, // the first ERRORS, as "line: why"
+}
+
+impl Malformed {
+    pub fn add(&mut self, line: usize, err: String) {
+        self.malformed += 1;
+        if self.errors.len() < ERRORS {
+            self.errors.push(format!("{line}: {err}"));
+        }
+    }
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct Report {
+    imported: usize,
+    #[serde(flatten)]
+    malformed: Malformed,
+}
+
+/// Adds every good line in `path` to `store`, using `app` and `pad` where a line has none.
+/// Bad lines are counted and reported, they dont stop the rest.
+/// The event codes are per platform, so a file from another os shows up here as malformed.
+pub fn import(store: &dyn EventStore, path: &str, app: &str, pad: &str) -> Result<Report, String> {
+    let mut report = Report::default();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+    read(path, &mut |n, line| {
+        match line.and_then(|line| line.check().map(|_| line)) {
+            Ok(line) => batch.push(line.into_event(app, pad)),
+            Err(err) => report.malformed.add(n, err),
+        }
+
+        if batch.len() >= BATCH_SIZE {
+            store.append(&batch)?;
+            report.imported += batch.len();
+            batch.clear();
+        }
+
+        Ok(())
+    })?;
+
+    store.append(&batch)?;
+    report.imported += batch.len();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use gilrs::{Axis, Button, EventType};
+
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::store::tests::{code, press, Scratch};
+
+    const AT: u128 = 1_700_002_800_000;
+
+    fn line(at: u128, event: EventType) -> Line {
+        Line { at, pad: None, app: None, event }
+    }
+
+    #[test]
+    fn checks_what_serde_lets_through() {
+        assert!(line(AT, EventType::AxisChanged(Axis::LeftStickX, -1.0, code())).check().is_ok());
+        assert!(line(AT, EventType::ButtonChanged(Button::South, 1.0, code())).check().is_ok());
+
+        assert!(line(5, EventType::Connected).check().is_err());
+        assert!(line(u128::MAX, EventType::Connected).check().is_err());
+        assert!(line(AT, EventType::AxisChanged(Axis::LeftStickX, 1.5, code())).check().is_err());
+        assert!(line(AT, EventType::AxisChanged(Axis::LeftStickX, f32::NAN, code())).check().is_err());
+        assert!(line(AT, EventType::ButtonChanged(Button::South, -0.1, code())).check().is_err());
+    }
+
+    #[test]
+    fn imports_good_lines_and_counts_the_rest() {
+        let scratch = Scratch::new("jsonl");
+        let path = scratch.path("events.jsonl");
+        let json = |line: Line| serde_json::to_string(&line).unwrap();
+        let lines = [
+            json(Line::from(press(AT, "game", Button::South))),
+            json(line(AT + 1, EventType::Connected)), // from before apps and pads were written
+            "{not json".to_string(),
+            String::new(),
+            json(line(5, EventType::Connected)),
+            json(line(AT + 2, EventType::ButtonChanged(Button::South, 2.0, code()))),
+        ];
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let store = MemoryStore::default();
+        let report = import(&store, &path, "old", "old pad").unwrap();
+        assert_eq!((report.imported, report.malformed.malformed), (2, 3));
+        let lines: Vec<&str> = report.malformed.errors.iter().map(|err| err.split(':').next().unwrap()).collect();
+        assert_eq!(lines, vec!["3", "5", "6"]);
+
+        let mut events = Vec::new();
+        store.scan(0, u128::MAX, &mut |event| {
+            events.push((event.at, event.app, event.pad));
+            true
+        }).unwrap();
+        assert_eq!(events, vec![
+            (AT + 1, "old".to_string(), "old pad".to_string()),
+            (AT, "game".to_string(), "pad".to_string()),
+        ]);
+    }
+}
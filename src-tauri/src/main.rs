@@ -5,26 +5,68 @@ use std::{collections::HashMap, sync::{atomic::Ordering, Arc}, time::{SystemTime
 use chrono::prelude::*;
 
 use flexi_logger::{Duplicate, FileSpec, WriteMode};
-use rocksdb::{DB, Options};
+use rocksdb::DB;
 
 use gilrs::{Event, Gilrs};
 use serde::{Deserialize, Serialize};
 
+mod aliases;
+mod backup;
+mod bundle;
+mod card;
+mod cli;
+mod controllers;
+mod dict;
+mod evdev;
+mod export;
+mod games;
+mod identity;
+mod index;
+mod integrity;
+mod jsonl;
+mod merge;
+mod migrate;
+mod paths;
+mod replay;
+mod retention;
+mod rollup;
+mod store;
+mod writer;
+#[cfg(target_os = "linux")]
+mod x11;
+
+use store::EventStore;
+
 use tauri::{CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem, WindowBuilder};
 
 // add combo
 // get app name for mac, cause fuck it
-// export, share
+// share
 
 struct Settings {
-    db: Arc<DB>,
+    store: Arc<store::Swap>,
     user_settings: Arc<std::sync::Mutex<UserSettings>>,
+    paths: paths::Paths,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 struct UserSettings {
     precision: f32,
     logging: String,
+    #[serde(default)]
+    retention: retention::Retention,
+    #[serde(default = "default_store")]
+    store: String, // rocksdb, sqlite or memory, takes a restart
+    #[serde(default)]
+    backups: backup::Backups,
+    #[serde(default)]
+    attribution: games::Attribution,
+    #[serde(default)]
+    aliases: Vec<aliases::Rule>, // applied when reading, apply_aliases makes them permanent
+}
+
+fn default_store() -> String {
+    "rocksdb".to_string()
 }
 
 #[derive(Default)]
@@ -42,15 +84,24 @@ struct Application {
     controller: String,
     presses: i32,
     combos: i32,
+    controllers: Vec<Played>, // most presses first
 }
 
+// how much of an app was played on one controller
 #[derive(Serialize)]
+struct Played {
+    id: String,   // the pad events are stored under, for app_stats
+    name: String, // the nickname, or what the controller calls itself
+    presses: i32,
+}
+
+#[derive(Serialize, Deserialize)]
 struct Button {
     name: gilrs::Button,
     presses: i32,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct Axis {
     name: gilrs::Axis,
     // pos: presses
@@ -66,14 +117,14 @@ struct Axis {
     h: f32, // i dont know on the frontend what they are, and all i have is the axis
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct Combo {
     name: String,
     pattern: Vec<String>,
     presses: i32,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct AppStats {
     name: String,
     presses: Vec<Button>,
@@ -81,15 +132,72 @@ struct AppStats {
     combos: Vec<Combo>,
 }
 
-const DB_VERSION: u8 = 1;
-#[derive(Serialize, Deserialize, Debug)]
+// used for buckets of the axes, i could use a unique value, but this is easier
+// not using prec, it could be 0 or too many
+const AXIS_H: f32 = 0.2;
+
+impl AppStats {
+    fn press(&mut self, button: gilrs::Button, n: i32) {
+        let pressed = self.presses.iter_mut().find(|press| press.name == button);
+        if let Some(pressed) = pressed {
+            pressed.presses += n;
+        } else {
+            self.presses.push(Button {
+                name: button,
+                presses: n,
+            });
+        }
+    }
+
+    fn axis(&mut self, axis: gilrs::Axis, bucket: i32, n: i32) {
+        if let Some(axis) = self.axes.iter_mut().find(|press| press.name == axis) {
+            // the axis exists, the map may not
+            *axis.pos_buckets.entry(bucket).or_default() += n;
+        } else {
+            // nothing exists
+            let mut pos_buckets: HashMap<i32, i32> = HashMap::new();
+            *pos_buckets.entry(bucket).or_default() += n;
+            self.axes.push(Axis { name: axis, pos_buckets, h: AXIS_H });
+        }
+    }
+}
+
+const DB_VERSION: u8 = 3;
+// [version, at (big endian), nonce]
+// big endian so rocks sorts the rows by time, the nonce only breaks ties within the same ms
+const KEY_LEN: usize = 1 + 16 + 2;
+
+fn encode_key(at: u128, nonce: u16) -> [u8; KEY_LEN] {
+    let mut pk: [u8; KEY_LEN] = [0; KEY_LEN];
+    pk[0] = DB_VERSION;
+    pk[1..17].copy_from_slice(&at.to_be_bytes());
+    pk[17..].copy_from_slice(&nonce.to_be_bytes());
+    pk
+}
+
+fn decode_key(key: &[u8]) -> Result<u128, String> {
+    if key.len() != KEY_LEN || key[0] != DB_VERSION {
+        return Err("Database version mismatch".to_string());
+    }
+
+    Ok(u128::from_be_bytes(key[1..17].try_into().unwrap()))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 struct Rock {
     at: u128,
-    pad: String,
-    app: String,
+    pad: u32, // dict id
+    app: u32, // dict id
     event: gilrs::EventType,
 }
 
+// the rock and everything derived from it go in one batch, so a crash cant leave them out of sync
+fn write_rock(db: &DB, batch: &mut rocksdb::WriteBatch, pk: &[u8], serialized: Vec<u8>, rock: &Rock) {
+    batch.put(pk, serialized);
+    rollup::record(db, batch, rock);
+    index::record(db, batch, pk, rock);
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 struct Point {
     data: u32,
@@ -97,7 +205,7 @@ struct Point {
     label: String,
 }
 
-fn _dummy_data(db: &DB) {
+fn _dummy_data(store: &dyn store::EventStore) {
     // open default json bad-id: 15.5MiB (111k)
     // open default bin: 2.7MiB (>100k)
     // open default json: 3.6MiB (100k)
@@ -121,6 +229,7 @@ fn _dummy_data(db: &DB) {
     let n = 100000;
     let t = 100;
     let unix_time = start.duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis() - (n as u128) * t;
+    let mut events = Vec::with_capacity(1000);
     for i in 0..n {
         let app = match rand::random::<u32>() % 5 {
             0 => "Skyrim".to_string(),
@@ -135,26 +244,22 @@ fn _dummy_data(db: &DB) {
         let event = serde_json::from_str(data).unwrap();
 
         let at = unix_time + (i as u128) * t;
-        let rock = Rock {
+        events.push(store::Event {
             at,
             pad: pad.clone(),
             app: app.clone(),
             event,
-        };
+        });
 
-        let serialized = bincode::serialize(&rock).unwrap();
-        let mut pk: [u8; 18] = [0; 18];
-        pk[0] = DB_VERSION;
-        pk[1] = 0;
-        pk[2..].copy_from_slice(&at.to_ne_bytes());
-        // let serialized = serde_json::to_string(&rock).unwrap();
         // i think doing 100_000 with time::now is too fast
         // somehow, using the same key gives more than one row
-        db.put(pk, serialized).unwrap();
+        if events.len() == events.capacity() {
+            store.append(&events).unwrap();
+            events.clear();
+        }
         // sleep(Duration::from_millis(100)); // wont do anything besides slow it down. im using unix_time as the key
-        // db.flush().unwrap(); // this will make the db big, but has no data
     }
-    db.flush().unwrap();
+    store.append(&events).unwrap();
     let end = SystemTime::now();
     log::info!("inserted {n} values in {:?}", end.duration_since(start).unwrap());
 }
@@ -168,64 +273,81 @@ async fn get_settings(state: tauri::State<'_, AppState>) -> Result<UserSettings,
 
 #[tauri::command]
 fn set_settings(user_settings: UserSettings, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    // a bad regex would otherwise only show up as every page failing
+    aliases::Aliases::compile(&user_settings.aliases)?;
+
     let mut settings = state.0.lock().unwrap();
-    *settings.as_mut().unwrap().user_settings.lock().unwrap() = user_settings.clone();
+    let settings = settings.as_mut().unwrap();
+    *settings.user_settings.lock().unwrap() = user_settings.clone();
 
     // write to file
     let settings_data = serde_json::to_string(&user_settings).unwrap();
-    std::fs::write("settings.json", settings_data).map_err(|err| err.to_string())?;
+    std::fs::write(settings.paths.settings(), settings_data).map_err(|err| err.to_string())?;
 
     Ok(())
 }
 
-#[tauri::command]
-async fn applications(timeframe: String, state: tauri::State<'_, AppState>) -> Result<Vec<Application>, String> {
-    let mut apps = Vec::<Application>::new();
-
-    let span = match timeframe.as_str() {
+fn span(timeframe: &str) -> u128 {
+    match timeframe {
         "day" => DAY,
         "week" => WEEK,
         "month" => MONTH,
         "year" => YEAR,
         _ => DAY,
-    };
-    let start = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis() - span;
-    
-    let db = state.0.lock().unwrap().as_ref().unwrap().db.clone();
+    }
+}
 
-    let iter = db.iterator(rocksdb::IteratorMode::End);
-    for row in iter {
-        let (key, value) = row.unwrap();
+// the store, and the alias rules as they are right now
+fn query(state: &tauri::State<'_, AppState>) -> Result<(Arc<store::Swap>, aliases::Aliases), String> {
+    let settings = state.0.lock().unwrap();
+    let settings = settings.as_ref().unwrap();
+    let aliases = aliases::Aliases::compile(&settings.user_settings.lock().unwrap().aliases)?;
+    Ok((settings.store.clone(), aliases))
+}
 
-        let version = key[0];
-        if version != DB_VERSION {
-            return Err("Database version mismatch".to_string());
-        }
+#[tauri::command]
+async fn applications(timeframe: String, state: tauri::State<'_, AppState>) -> Result<Vec<Application>, String> {
+    let (store, aliases) = query(&state)?;
+    applications_for(store.as_ref(), &aliases, &timeframe)
+}
 
-        let t = &key[2..];
+fn applications_for(store: &dyn EventStore, aliases: &aliases::Aliases, timeframe: &str) -> Result<Vec<Application>, String> {
+    let mut apps = Vec::<Application>::new();
 
-        // skip if to old
-        let at = u128::from_ne_bytes(t.to_vec().try_into().unwrap());
-        if at < start {
-            break; // we are reversed, so we can break
-        }
+    let span = span(timeframe);
+    let start = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis() - span;
 
-        // let value = String::from_utf8(value.into_vec()).unwrap();
-        let rock: Rock = bincode::deserialize(&value.into_vec()).unwrap();
+    let labels = controllers::labels(store)?;
+    let label = |pad: &str| labels.get(pad).cloned().unwrap_or_else(|| pad.to_string());
+
+    // same precision as a day on the graph
+    for count in store.counts(start, span / 24 / 24)? {
+        let name = aliases.canonical(store, &count.app);
+        let app = match apps.iter_mut().position(|app| app.name == name) {
+            Some(app) => &mut apps[app],
+            None => {
+                apps.push(Application {
+                    name,
+                    controller: label(&count.pad),
+                    presses: 0,
+                    combos: 0,
+                    controllers: Vec::new(),
+                });
+                apps.last_mut().unwrap()
+            }
+        };
 
-        let app = apps.iter_mut().find(|app| app.name == rock.app);
-        if let Some(app) = app {
-            app.presses += 1;
-        } else {
-            apps.push(Application {
-                name: rock.app,
-                controller: rock.pad,
-                presses: 1,
-                combos: 0,
-            });
+        app.presses += count.count as i32;
+        match app.controllers.iter_mut().find(|played| played.id == count.pad) {
+            Some(played) => played.presses += count.count as i32,
+            None => app.controllers.push(Played { name: label(&count.pad), id: count.pad, presses: count.count as i32 }),
         }
     }
 
+    for app in &mut apps {
+        app.controllers.sort_by(|a, b| b.presses.cmp(&a.presses));
+    }
+
     Ok(apps)
 }
 
@@ -237,7 +359,7 @@ const WEEK: u128 = 7 * DAY; // 604_800_000 ms
 const MONTH: u128 = 30 * DAY; // 2_592_000_000 ms
 const YEAR: u128 = 365 * DAY; // 31_536_000_000 ms
 
-fn past_time(span: u128, n: u128, form: &str, state: tauri::State<'_, AppState>) -> Result<Vec<Point>, String> {
+fn past_time(span: u128, n: u128, form: &str, store: &dyn EventStore) -> Result<Vec<Point>, String> {
     let start = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis() - span;
 
     // create the buckets
@@ -254,32 +376,18 @@ fn past_time(span: u128, n: u128, form: &str, state: tauri::State<'_, AppState>)
         })
     }
 
-    let db = state.0.lock().unwrap().as_ref().unwrap().db.clone();
-
-    for row in db.iterator(rocksdb::IteratorMode::End) {
-        let (key, _value) = row.unwrap();
-
-        let version = key[0];
-        if version != DB_VERSION {
-            return Err("Database version mismatch".to_string());
-        }
-
-        let t = &key[2..];
-        let at = u128::from_ne_bytes(t.to_vec().try_into().unwrap());
+    // a point is off by at most 1/24th of its width
+    for count in store.counts(start, span / n / 24)? {
+        let at = count.bucket;
 
         // add the data to the proper bucket
         for i in 0..n as usize {
             // this will deal with oob
             if at >= buckets[i].at && at < buckets[i].at + (span / n) {
-                buckets[i].data += 1;
+                buckets[i].data += count.count as u32;
                 break;
             }
         }
-
-        // check if we are no longer in bounds
-        if at < start {
-            break;
-        }
     }
 
     Ok(buckets)
@@ -287,19 +395,31 @@ fn past_time(span: u128, n: u128, form: &str, state: tauri::State<'_, AppState>)
 
 #[tauri::command]
 async fn graph(timeframe: String, state: tauri::State<'_, AppState>) -> Result<Vec<Point>, String> {
-    let points = match timeframe.as_str() {
-        "day" => past_time(DAY, 24, "%l %P", state),
-        "week" => past_time(WEEK, 7, "%a", state),
-        "month" => past_time(MONTH, 30, "%e", state), // hmmmm
-        "year" => past_time(YEAR, 12, "%b", state),
-        _ => past_time(DAY, 24, "%l %P", state),
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+    graph_for(store.as_ref(), &timeframe)
+}
+
+fn graph_for(store: &dyn EventStore, timeframe: &str) -> Result<Vec<Point>, String> {
+    let points = match timeframe {
+        "day" => past_time(DAY, 24, "%l %P", store),
+        "week" => past_time(WEEK, 7, "%a", store),
+        "month" => past_time(MONTH, 30, "%e", store), // hmmmm
+        "year" => past_time(YEAR, 12, "%b", store),
+        _ => past_time(DAY, 24, "%l %P", store),
     };
 
     points
 }
 
+// controller is an id from Application.controllers, to only count that one
 #[tauri::command]
-async fn app_stats(app: String, timeframe: String, state: tauri::State<'_, AppState>) -> Result<AppStats, String> {
+async fn app_stats(app: String, timeframe: String, controller: Option<String>, state: tauri::State<'_, AppState>) -> Result<AppStats, String> {
+    let (store, aliases) = query(&state)?;
+    app_stats_for(store.as_ref(), &aliases, app, &timeframe, controller.as_deref())
+}
+
+// app is a name from applications, so with aliases it can be several stored apps added up
+fn app_stats_for(store: &dyn EventStore, aliases: &aliases::Aliases, app: String, timeframe: &str, pad: Option<&str>) -> Result<AppStats, String> {
     let mut app =  AppStats {
         name: app,
         presses: Vec::new(),
@@ -307,80 +427,286 @@ async fn app_stats(app: String, timeframe: String, state: tauri::State<'_, AppSt
         combos: Vec::new(),
     };
 
-    // used for buckets of the axes, i could use a unique value, but this is easier
-    // not using prec, it could be 0 or too many
-    let h = 0.2;
+    let start = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis() - span(timeframe);
 
-    let span = match timeframe.as_str() {
-        "day" => DAY,
-        "week" => WEEK,
-        "month" => MONTH,
-        "year" => YEAR,
-        _ => DAY,
-    };
-    
-    let start = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis() - span;
+    // this will be auto formatted by serde when going to js
+    // this really has all the events i care about
+    let mut summary = store::Summary::default();
+    for member in aliases.members(store, &app.name, start)? {
+        match pad {
+            Some(pad) => summary.merge(store.summary_by_pad(&member, start)?.remove(pad).unwrap_or_default()),
+            None => summary.merge(store.summary(&member, start)?),
+        }
+    }
+    for (button, presses) in summary.presses {
+        app.press(button, presses as i32);
+    }
 
-    let db = state.0.lock().unwrap().as_ref().unwrap().db.clone();
+    for (axis, buckets) in summary.axes {
+        for (bucket, n) in buckets {
+            app.axis(axis, bucket, n as i32);
+        }
+    }
+
+    Ok(app)
+}
 
-    for row in db.iterator(rocksdb::IteratorMode::End) {
-        let (key, value) = row.unwrap();
+// name is what the game shows as on their end, the window title never leaves
+#[tauri::command]
+async fn share_bundle(app: String, name: String, timeframe: String, path: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let (store, aliases) = query(&state)?;
+    bundle::save(&bundle::create(store.as_ref(), &aliases, &app, &name, &timeframe)?, &path)
+}
 
-        let version = key[0];
-        if version != DB_VERSION {
-            return Err("Database version mismatch".to_string());
-        }
+// read only, for showing next to our own app_stats
+#[tauri::command]
+async fn open_bundle(path: String) -> Result<bundle::Bundle, String> {
+    bundle::open(&path)
+}
 
-        // drop the first 2 bytes
-        let t = &key[2..];
-        let at = u128::from_ne_bytes(t.to_vec().try_into().unwrap());
+// the csv commands write the same numbers as the pages, the columns are in export.rs
 
-        // check if we are no longer in bounds
-        if at < start {
-            break;
-        }
+#[tauri::command]
+async fn applications_csv(timeframe: String, path: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let (store, aliases) = query(&state)?;
+    export::applications_csv(&applications_for(store.as_ref(), &aliases, &timeframe)?, &path)
+}
 
-        // let value = String::from_utf8(value.into_vec()).unwrap();
-        let rock: Rock = bincode::deserialize(&value.into_vec()).unwrap();
-        if rock.app != app.name {
-            continue;
-        }
+#[tauri::command]
+async fn graph_csv(timeframe: String, path: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+    export::graph_csv(&graph_for(store.as_ref(), &timeframe)?, &path)
+}
 
-        // this will be auto formatted by serde when going to js
-        // this really has all the events i care about
-        match rock.event {
-            gilrs::EventType::ButtonPressed(button, _code) => {
-                let pressed = app.presses.iter_mut().find(|press| press.name == button);
-                if let Some(pressed) = pressed {
-                    pressed.presses += 1;
-                } else {
-                    app.presses.push(Button {
-                        name: button,
-                        presses: 1,
-                    });
-                }
-            },
-            gilrs::EventType::AxisChanged(axis, pos, _code) => {
-                let bucket = (pos/h).floor() as i32;
-
-                if let Some(axis) = app.axes.iter_mut().find(|press| press.name == axis) {
-                    // the axis exists, the map may not
-                    *axis.pos_buckets.entry(bucket).or_default() += 1;
-                } else {
-                    // nothing exists
-                    let mut pos_buckets: HashMap<i32, i32> = HashMap::new();
-                    *pos_buckets.entry(bucket).or_default() += 1;
-                    app.axes.push(Axis { name: axis, pos_buckets, h });
-                }
-            },
-            _ => {}
-        }
+#[tauri::command]
+async fn app_stats_csv(app: String, timeframe: String, path: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let (store, aliases) = query(&state)?;
+    export::app_stats_csv(&app_stats_for(store.as_ref(), &aliases, app, &timeframe, None)?, &path)
+}
+
+#[tauri::command]
+async fn rebuild_rollups(state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+    store.rebuild()
+}
+
+// with quarantine the bad rows are moved out of the way, otherwise this only reports them
+#[tauri::command]
+async fn check_integrity(quarantine: bool, state: tauri::State<'_, AppState>) -> Result<integrity::Report, String> {
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+    let report = store.check(quarantine)?;
+    log::info!("integrity check: {report:?}");
+    Ok(report)
+}
+
+// path is another coca-rocks.db or a jsonl export, prefer is "ours" (default) or "theirs"
+#[tauri::command]
+async fn merge(path: String, prefer: Option<String>, state: tauri::State<'_, AppState>) -> Result<merge::Report, String> {
+    let prefer = merge::Prefer::parse(prefer.as_deref())?;
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+
+    let report = merge::merge(store.as_ref(), &path, prefer)?;
+    log::info!("merged {path}: {report:?}");
+    Ok(report)
+}
+
+// returns how many events were written
+#[tauri::command]
+async fn export_jsonl(path: String, filter: export::Filter, state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+
+    let written = export::jsonl(store.as_ref(), &filter, &path)?;
+    log::info!("exported {written} events matching {filter:?} to {path}");
+    Ok(written)
+}
+
+// returns how many events were written
+#[tauri::command]
+async fn export_parquet(path: String, filter: export::Filter, state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+
+    let written = export::parquet(store.as_ref(), &filter, &path)?;
+    log::info!("exported {written} events matching {filter:?} to {path}");
+    Ok(written)
+}
+
+// app and pad fill in lines that dont say, "?" if not given
+#[tauri::command]
+async fn import_jsonl(path: String, app: Option<String>, pad: Option<String>, state: tauri::State<'_, AppState>) -> Result<jsonl::Report, String> {
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+
+    let report = jsonl::import(store.as_ref(), &path, app.as_deref().unwrap_or("?"), pad.as_deref().unwrap_or("?"))?;
+    log::info!("imported {path}: {report:?}");
+    Ok(report)
+}
+
+// png or svg by the extension of path, every app when app is None
+#[tauri::command]
+async fn stat_card(timeframe: String, app: Option<String>, path: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let (store, aliases) = query(&state)?;
+
+    card::render(store.as_ref(), &aliases, &timeframe, app.as_deref(), &path)?;
+    log::info!("wrote a {timeframe} stat card to {path}");
+    Ok(())
+}
+
+// from and to are ms since the unix epoch, returns how many events went in
+#[tauri::command]
+async fn record_replay(from: u128, to: u128, app: Option<String>, path: String, state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+
+    let replay = replay::record(store.as_ref(), from, to, app.as_deref())?;
+    replay::save(&replay, &path)?;
+    log::info!("recorded {} events from {from}..{to} to {path}", replay.events());
+    Ok(replay.events())
+}
+
+#[tauri::command]
+async fn load_replay(path: String, player: tauri::State<'_, Arc<replay::Player>>) -> Result<replay::Status, String> {
+    Ok(player.load(replay::open(&path)?))
+}
+
+// speed is times real time, the last one used if not given
+#[tauri::command]
+fn play_replay(speed: Option<f64>, player: tauri::State<'_, Arc<replay::Player>>) -> Result<replay::Status, String> {
+    player.play(speed)
+}
+
+#[tauri::command]
+fn pause_replay(player: tauri::State<'_, Arc<replay::Player>>) -> replay::Status {
+    player.pause()
+}
+
+// position is ms from the start of the replay
+#[tauri::command]
+fn seek_replay(position: u64, app: tauri::AppHandle, player: tauri::State<'_, Arc<replay::Player>>) -> Result<replay::Status, String> {
+    player.seek(&app, position)
+}
+
+#[tauri::command]
+fn replay_status(player: tauri::State<'_, Arc<replay::Player>>) -> replay::Status {
+    player.status()
+}
+
+// app is "?" if not given, start is ms since the unix epoch, see evdev::import
+#[tauri::command]
+async fn import_evdev(path: String, app: Option<String>, start: Option<u128>, state: tauri::State<'_, AppState>) -> Result<evdev::Report, String> {
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+
+    let report = evdev::import(store.as_ref(), &path, app.as_deref().unwrap_or("?"), start)?;
+    log::info!("imported {path}: {report:?}");
+    Ok(report)
+}
+
+// the exe, last pid and raw titles seen for app, null if nothing was
+#[tauri::command]
+async fn app_identity(app: String, state: tauri::State<'_, AppState>) -> Result<Option<identity::Known>, String> {
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+    store.known(&app)
+}
+
+// what apply_aliases would rename, and how many events each has
+#[tauri::command]
+async fn preview_aliases(state: tauri::State<'_, AppState>) -> Result<Vec<aliases::Rename>, String> {
+    let (store, aliases) = query(&state)?;
+    aliases.preview(store.as_ref())
+}
+
+// rewrites the stored app names for good, there is no undo besides a backup
+#[tauri::command]
+async fn apply_aliases(state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    let (store, aliases) = query(&state)?;
+
+    let moved = aliases.rewrite(store.as_ref())?;
+    log::info!("moved {moved} events to their aliased app names");
+    Ok(moved)
+}
+
+#[tauri::command]
+async fn controllers(state: tauri::State<'_, AppState>) -> Result<Vec<controllers::Controller>, String> {
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+    store.controllers()
+}
+
+// an empty or missing nickname goes back to the name the controller gives
+#[tauri::command]
+async fn rename_controller(id: String, nickname: Option<String>, state: tauri::State<'_, AppState>) -> Result<controllers::Controller, String> {
+    let store = state.0.lock().unwrap().as_ref().unwrap().store.clone();
+    controllers::rename(store.as_ref(), &id, nickname)
+}
+
+#[tauri::command]
+async fn create_backup(state: tauri::State<'_, AppState>) -> Result<backup::Backup, String> {
+    let (store, dir, keep) = {
+        let settings = state.0.lock().unwrap();
+        let settings = settings.as_ref().unwrap();
+        let backups = settings.user_settings.lock().unwrap().backups.clone();
+        (settings.store.clone(), settings.paths.backups(&backups), backups.keep)
+    };
+
+    store.backup(&dir, keep)
+}
+
+#[tauri::command]
+async fn list_backups(state: tauri::State<'_, AppState>) -> Result<Vec<backup::Backup>, String> {
+    let dir = {
+        let settings = state.0.lock().unwrap();
+        let settings = settings.as_ref().unwrap();
+        let backups = settings.user_settings.lock().unwrap().backups.clone();
+        settings.paths.backups(&backups)
+    };
+    backup::list(&dir)
+}
+
+// returns where the replaced db was moved to
+#[tauri::command]
+async fn restore_backup(id: u32, state: tauri::State<'_, AppState>) -> Result<String, String> {
+    let (store, paths, kind, backups) = {
+        let settings = state.0.lock().unwrap();
+        let settings = settings.as_ref().unwrap();
+        let user_settings = settings.user_settings.lock().unwrap();
+        (settings.store.clone(), settings.paths.clone(), user_settings.store.clone(), user_settings.backups.clone())
+    };
+
+    if kind != "rocksdb" {
+        return Err(format!("backups are only kept for the rocksdb store, this is using {kind}"));
     }
 
-    Ok(app)
+    let aside = backup::restore(&store, &paths.backups(&backups), id, &paths.rocks())?;
+    log::info!("restored backup {id}, the old db is in {aside}");
+    Ok(aside)
 }
 
-static FOCUSED_APP: std::sync::Mutex<String> = std::sync::Mutex::new(String::new());
+// the gilrs thread turns this into the app name with identity::Identity::app
+static FOCUSED_APP: std::sync::Mutex<identity::Identity> = std::sync::Mutex::new(identity::Identity::unknown());
+
+// the window x11 last saw focused, kept while the running game is used so switching back has it straight away
+#[cfg(target_os = "linux")]
+static FOCUSED_WINDOW: std::sync::Mutex<Option<identity::Identity>> = std::sync::Mutex::new(None);
+
+// the exe behind a window, if we are allowed to ask its process
+#[cfg(windows)]
+unsafe fn window_exe(hwnd: windows::Win32::Foundation::HWND) -> (Option<u32>, Option<String>) {
+    use windows::Win32::System::Threading::{OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION};
+
+    let mut pid = 0;
+    windows::Win32::UI::WindowsAndMessaging::GetWindowThreadProcessId(hwnd, Some(&mut pid));
+    if pid == 0 {
+        return (None, None);
+    }
+
+    // elevated processes say no, then all we have is the title
+    let Ok(process) = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) else {
+        return (Some(pid), None);
+    };
+
+    let mut exe = [0u16; 1024];
+    let mut len = exe.len() as u32;
+    let found = QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, windows::core::PWSTR(exe.as_mut_ptr()), &mut len);
+    let _ = windows::Win32::Foundation::CloseHandle(process);
+
+    (Some(pid), found.ok().map(|_| String::from_utf16_lossy(&exe[..len as usize])))
+}
 
 #[cfg(windows)]
 unsafe extern "system" fn win_event_proc(
@@ -396,31 +722,46 @@ unsafe extern "system" fn win_event_proc(
     let len = windows::Win32::UI::WindowsAndMessaging::GetWindowTextW(hwnd, &mut title);
     let title = String::from_utf16_lossy(&title[..len as usize]);
 
-    let mut last_window = FOCUSED_APP.lock().unwrap();
-    *last_window = if title.is_empty() {
-        "Windows".to_string()
-    } else {
-        title.clone()
+    let (pid, exe) = window_exe(hwnd);
+    let focus = identity::Identity {
+        title: if title.is_empty() { "Windows".to_string() } else { title },
+        exe,
+        pid,
     };
-    
-    log::debug!("Focus changed to: {}", title);
+
+    log::debug!("Focus changed to: {focus:?}");
+    *FOCUSED_APP.lock().unwrap() = focus;
 }
 
 fn main() {
-    // read settings json file
-    let settings_data = std::fs::read_to_string("settings.json").unwrap_or_else(|_| {
-        let default = UserSettings {
-            precision: 0.0,
-            logging: "off".to_string(),
-        };
-        serde_json::to_string(&default).unwrap()
-    });
-
-    let user_settings: Result<UserSettings, _> = serde_json::from_str(&settings_data);
-    let user_settings = Arc::new(std::sync::Mutex::new(user_settings.unwrap()));
+    let context = tauri::generate_context!();
+
+    // everything lives in the platform dirs, so it doesnt matter where we are started from
+    let paths = paths::Paths::resolve(context.config()).unwrap();
+    let adopted = paths.adopt();
+
+    // read settings json file, a missing or broken one starts from the defaults
+    let default = UserSettings {
+        precision: 0.0,
+        logging: "off".to_string(),
+        retention: retention::Retention::default(),
+        store: default_store(),
+        backups: backup::Backups::default(),
+        attribution: games::Attribution::default(),
+        aliases: Vec::new(),
+    };
+    let mut broken = None; // logged once the logger is up
+    let user_settings = match std::fs::read_to_string(paths.settings()) {
+        Ok(data) => serde_json::from_str(&data).unwrap_or_else(|err| {
+            broken = Some(format!("{} isnt valid settings, using the defaults: {err}", paths.settings()));
+            default
+        }),
+        Err(_) => default,
+    };
+    let user_settings = Arc::new(std::sync::Mutex::new(user_settings));
 
     let  _logger = flexi_logger::Logger::try_with_env_or_str(user_settings.lock().unwrap().logging.clone()).unwrap()
-        .log_to_file(FileSpec::default()) // write logs to file
+        .log_to_file(FileSpec::default().directory(paths.logs())) // write logs to file
         .write_mode(WriteMode::BufferAndFlush)
         .duplicate_to_stdout(if cfg!(debug_assertions) {
             Duplicate::All
@@ -428,17 +769,140 @@ fn main() {
             Duplicate::None
         }).start().unwrap();
 
-    let path = "coca-rocks.db";
-    let mut opts = Options::default();
-    opts.create_if_missing(true);
-    // open default: 15.5MiB (111k)
-    let db = Arc::new(DB::open(&opts, path).unwrap());
-    
-    // check if the db is the proper version
+    if let Some(broken) = broken {
+        log::error!("{broken}");
+    }
+    for note in adopted {
+        log::info!("{note}");
+    }
+
+    // --dry-run-migrations only says what would change to the rocks db, then quits
+    if cli::has("--dry-run-migrations") {
+        let db = match store::rocks::open_db(&paths.rocks()) {
+            Ok(db) => db,
+            Err(err) => {
+                eprintln!("failed to open the db, is coca still running? quit it first: {err}");
+                std::process::exit(1);
+            }
+        };
+        let on_progress = |p: &migrate::Progress| println!("{} (v{} -> v{}): {}/~{} rows", p.name, p.from, p.to, p.done, p.total);
+        match migrate::run(&db, true, &on_progress) {
+            Ok(report) => {
+                println!("would migrate {} rows from db version {} to {}", report.rewritten, report.from, report.to);
+                if report.quarantined > 0 {
+                    println!("would quarantine {} rows that cant be read", report.quarantined);
+                }
+                for step in report.unchecked {
+                    println!("not checked: {step}, it needs the rows the step before writes, migrating for real (a checkpoint is made first) runs it");
+                }
+            }
+            Err(err) => {
+                eprintln!("migration would fail: {err}");
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
+    // --migrate-again runs the migrations over rows an earlier one left behind, then starts as usual
+    if cli::has("--migrate-again") {
+        match store::rocks::open_db(&paths.rocks()).and_then(|db| migrate::reset(&db)) {
+            Ok(()) => println!("migrating any rows from older db versions again"),
+            Err(err) => {
+                eprintln!("failed to open the db, is coca still running? quit it first: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let kind = user_settings.lock().unwrap().store.clone();
+    let store: Arc<dyn store::EventStore> = match kind.as_str() {
+        "sqlite" => Arc::new(store::sqlite::SqliteStore::open(&paths.sqlite()).unwrap()),
+        "memory" => Arc::new(store::memory::MemoryStore::default()),
+        _ => match store::rocks::RocksStore::open(&paths.rocks()) {
+            Ok(store) => Arc::new(store),
+            Err(err) => {
+                // the one other thing holding the LOCK file is a coca thats still running
+                eprintln!("failed to open the db, is coca still running? quit it first: {err}");
+                std::process::exit(1);
+            }
+        },
+    };
+    // everything holds the swap, so a restored backup can be put in without a restart
+    let store = Arc::new(store::Swap::new(store));
+
+    // --export <file> writes the events to jsonl (or parquet for a .parquet file) and quits
+    // see export::Filter::from_args for the filters
+    if let Some(path) = cli::value("--export") {
+        let exported = export::Filter::from_args().and_then(|filter| {
+            if path.ends_with(".parquet") {
+                export::parquet(store.as_ref(), &filter, &path)
+            } else {
+                export::jsonl(store.as_ref(), &filter, &path)
+            }
+        });
+        match exported {
+            Ok(written) => println!("exported {written} events to {path}"),
+            Err(err) => {
+                eprintln!("failed to export: {err}");
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
+    // --import <file> adds the events from a jsonl file and quits
+    // lines without an app or pad get --default-app and --default-pad
+    if let Some(path) = cli::value("--import") {
+        let app = cli::value("--default-app").unwrap_or_else(|| "?".to_string());
+        let pad = cli::value("--default-pad").unwrap_or_else(|| "?".to_string());
+        match jsonl::import(store.as_ref(), &path, &app, &pad) {
+            Ok(report) => println!("imported {path}: {report:?}"),
+            Err(err) => {
+                eprintln!("failed to import: {err}");
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
+    // --import-evdev <file> adds an evtest or evemu-record dump and quits
+    // --default-app names the app, --start places an evemu recording (see cli::parse_time)
+    if let Some(path) = cli::value("--import-evdev") {
+        let app = cli::value("--default-app").unwrap_or_else(|| "?".to_string());
+        let imported = cli::value("--start")
+            .map(|start| cli::parse_time(&start))
+            .transpose()
+            .and_then(|start| evdev::import(store.as_ref(), &path, &app, start));
+        match imported {
+            Ok(report) => println!("imported {path}: {report:?}"),
+            Err(err) => {
+                eprintln!("failed to import: {err}");
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
+    // --card <file> writes a stat card (png or svg by the extension) and quits, no window needed
+    // --timeframe defaults to week, --app picks one app instead of all of them
+    if let Some(path) = cli::value("--card") {
+        let timeframe = cli::value("--timeframe").unwrap_or_else(|| "week".to_string());
+        let rendered = aliases::Aliases::compile(&user_settings.lock().unwrap().aliases)
+            .and_then(|aliases| card::render(store.as_ref(), &aliases, &timeframe, cli::value("--app").as_deref(), &path));
+        match rendered {
+            Ok(()) => println!("wrote a {timeframe} stat card to {path}"),
+            Err(err) => {
+                eprintln!("failed to make the card: {err}");
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
 
     {
         let mut last_window = FOCUSED_APP.lock().unwrap();
-        *last_window = "?".to_string();
+        *last_window = identity::Identity::titled("?");
     }
 
     #[cfg(windows)]
@@ -468,43 +932,95 @@ fn main() {
         }
     });
 
-    // _dummy_data(&db);
+    // without a display (a server, or wayland with no xwayland) every event stays on "?"
+    // the setting is checked on every change and games puts the last window back, so switching sources doesnt need a restart
+    #[cfg(target_os = "linux")]
+    let settings_x11 = Arc::clone(&user_settings);
+    #[cfg(target_os = "linux")]
+    let _x11_thread = std::thread::spawn(move || {
+        let result = x11::watch(&mut |mut focus| {
+            log::debug!("Focus changed to: {focus:?}");
+            if focus.title.is_empty() {
+                focus.title = "Linux".to_string();
+            }
+
+            // held while FOCUSED_APP is set, so games cant put back an older window in between
+            let mut window = FOCUSED_WINDOW.lock().unwrap();
+            *window = Some(focus.clone());
+            if !settings_x11.lock().unwrap().attribution.uses_processes() {
+                *FOCUSED_APP.lock().unwrap() = focus;
+            }
+        });
+        if let Err(err) = result {
+            log::warn!("not tracking the focused window: {err}");
+        }
+    });
+
+    // wayland has no way to ask for the focused window, so look for what game is running instead
+    #[cfg(target_os = "linux")]
+    games::spawn(Arc::clone(&user_settings), &FOCUSED_APP, &FOCUSED_WINDOW);
+
+    // _dummy_data(store.as_ref());
     // std::process::exit(0);
 
+    retention::spawn(store.clone(), Arc::clone(&user_settings));
+
+    // the other stores cant be backed up, no point waking up for them
+    if kind == "rocksdb" {
+        backup::spawn(store.clone(), Arc::clone(&user_settings), paths.clone());
+    }
+
+    // everything captured goes through the writer, so nothing is lost on quit
+    let writer = writer::Writer::spawn(store.clone());
+    let writer_put = writer.clone();
+    let writer_quit = writer.clone();
+    let writer_exit = writer.clone();
+
     // run gilrs in a separate thread
-    let db_put = Arc::clone(&db);
     let settings_put = Arc::clone(&user_settings);
+    let store_put = store.clone();
     let _gilrs_thread = std::thread::spawn(move || {
         let mut gilrs = Gilrs::new().unwrap();
 
-        // Iterate over all connected gamepads
-        for (_id, gamepad) in gilrs.gamepads() {
+        // every connected gamepad => the controller id its events are stored under
+        let mut pads = std::collections::HashMap::<gilrs::GamepadId, String>::new();
+
+        // Iterate over all connected gamepads, these dont get a Connected event
+        for (id, gamepad) in gilrs.gamepads() {
             log::debug!("{} is {:?}", gamepad.name(), gamepad.power_info());
+            let controller = controllers::connect(store_put.as_ref(), &gamepad, &pads);
+            pads.insert(id, controller.id);
         }
 
-        let mut pad = "?".to_string();
-        // create map for the events
-        let mut past_buttons = std::collections::HashMap::<gilrs::Button, f32>::new();
-        let mut past_axes = std::collections::HashMap::<gilrs::Axis, f32>::new();
+        let mut last_focus = None;
+        let mut app = "?".to_string();
+        // create map for the events, per pad so two of them dont filter each other out
+        let mut past_buttons = std::collections::HashMap::<(gilrs::GamepadId, gilrs::Button), f32>::new();
+        let mut past_axes = std::collections::HashMap::<(gilrs::GamepadId, gilrs::Axis), f32>::new();
 
-        let mut nonce = 0; // i think this is the right thing, rather than salt/pepper
-        let mut pk: [u8; 18] = [0; 18];
-        pk[0] = DB_VERSION;
         loop {
             // Examine new events
             while let Some(Event { id, event, time }) = gilrs.next_event_blocking(Some(std::time::Duration::from_millis(100))) {
                 // check if it is a connection event
                 if event == gilrs::ev::EventType::Connected {
                     let gamepad = gilrs.gamepad(id);
-                    pad = gamepad.name().to_string();
-                    
-                    log::debug!("connected: {:?}; power: {:?}; ff: {:?}", pad, gamepad.power_info(), gamepad.is_ff_supported());
+                    let controller = controllers::connect(store_put.as_ref(), &gamepad, &pads);
+
+                    log::debug!("connected: {:?}; power: {:?}; ff: {:?}", controller, gamepad.power_info(), gamepad.is_ff_supported());
+                    pads.insert(id, controller.id);
                 }
 
+                // the disconnect still goes under the pad, but its id is free for the next one of the same model
+                let pad = match event {
+                    gilrs::EventType::Disconnected => pads.remove(&id),
+                    _ => pads.get(&id).cloned(),
+                }
+                .unwrap_or_else(|| "?".to_string());
+
                 match event {
                     gilrs::EventType::AxisChanged(axis, value, _code) => {
                         let prec = settings_put.lock().unwrap().precision;
-                        if let Some(past_value) = past_axes.get(&axis) {
+                        if let Some(past_value) = past_axes.get(&(id, axis)) {
                             // better than -> value > past + prec || value < past - prec
                             if (value - past_value).abs() < prec {
                                 log::trace!("skipping axis");
@@ -512,49 +1028,44 @@ fn main() {
                             }
                         }
 
-                        past_axes.insert(axis, value);
+                        past_axes.insert((id, axis), value);
                     }
                     gilrs::EventType::ButtonChanged(button, value, _code) => {
                         let prec = settings_put.lock().unwrap().precision;
-                        if let Some(past_value) = past_buttons.get(&button) {
+                        if let Some(past_value) = past_buttons.get(&(id, button)) {
                             if (value - past_value).abs() < prec {
                                 log::trace!("skipping button");
                                 continue;
                             }
                         }
 
-                        past_buttons.insert(button, value);
+                        past_buttons.insert((id, button), value);
                     }
                     _ => {}
                 }
 
-                let app = FOCUSED_APP.lock().unwrap();
+                // only worked out again when focus moves, and thats when the store hears about it
+                let focused = FOCUSED_APP.lock().unwrap().clone();
+                if last_focus.as_ref() != Some(&focused) {
+                    app = focused.app();
+                    if let Err(err) = store_put.identify(&app, &focused) {
+                        log::error!("failed to remember what {app} is: {err}");
+                    }
+                    last_focus = Some(focused);
+                }
 
                 let unix_time = time.duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis();
-                let rock = Rock {
+                let rock = store::Event {
                     at: unix_time,
-                    pad: pad.clone(),
+                    pad,
                     app: app.clone(),
                     event,
                 };
 
-                let serialized = bincode::serialize(&rock).unwrap();
-
-                // println!("serialized = {:?}", serialized);
-
-                // not sure if this is better than allocating a new vec
-                pk[1] = nonce;
-                pk[2..].copy_from_slice(&unix_time.to_ne_bytes());
-                // there can be multiple with the same nonce, as long as they arent at the same time
-                // this doesnt need to be in the struct, because i dont need it
-                if nonce == 255 {
-                    nonce = 0;
-                } else {
-                    nonce += 1;
-                }
-
-                db_put.put(pk, serialized).unwrap();
                 log::trace!("{rock:?}");
+                if let Err(err) = writer_put.send(rock) {
+                    log::error!("dropped rock: {err}");
+                }
             }
         }
     });
@@ -563,12 +1074,18 @@ fn main() {
     let visible_c = Arc::clone(&visible);
     let visible_c1 = Arc::clone(&visible);
     let hide = CustomMenuItem::new("toggle".to_string(), "Hide"); // i know the state
+    let backup_now = CustomMenuItem::new("backup".to_string(), "Back up now");
     let quit = CustomMenuItem::new("quit".to_string(), "Quit");
     let tray_menu = SystemTrayMenu::new()
         .add_item(hide)
+        .add_item(backup_now)
         .add_native_item(SystemTrayMenuItem::Separator)
         .add_item(quit);
 
+    let store_tray = Arc::clone(&store);
+    let settings_tray = Arc::clone(&user_settings);
+    let paths_tray = paths.clone();
+
     tauri::Builder::default()
         .system_tray(SystemTray::new().with_menu(tray_menu))
         .on_system_tray_event(move |app, event| match event {
@@ -597,8 +1114,21 @@ fn main() {
                 let item_handle = app.tray_handle().get_item(&id);
                 match id.as_str() {
                 "quit" => {
+                    if let Err(err) = writer_quit.flush() {
+                        log::error!("failed to flush before quitting: {err}");
+                    }
                     std::process::exit(0);
                 }
+                "backup" => {
+                    let store = Arc::clone(&store_tray);
+                    let backups = settings_tray.lock().unwrap().backups.clone();
+                    let (dir, keep) = (paths_tray.backups(&backups), backups.keep);
+                    // flushing a big db can take a bit, dont hold up the tray
+                    std::thread::spawn(move || match store.backup(&dir, keep) {
+                        Ok(backup) => log::info!("backed up the db: {backup:?}"),
+                        Err(err) => log::error!("failed to back up the db: {err}"),
+                    });
+                }
                 "toggle" => {
                     let window = app.get_window("main").unwrap_or_else(|| {
                         let w = WindowBuilder::new(app, "main", tauri::WindowUrl::App("index.html".into()))
@@ -628,9 +1158,13 @@ fn main() {
             }
             _ => {}
         })
-        .manage(AppState(std::sync::Arc::new(std::sync::Mutex::new(Some(Settings { db, user_settings: Arc::clone(&user_settings) })))))
-        .invoke_handler(tauri::generate_handler![greet, applications, graph, app_stats, get_settings, set_settings])
-        .build(tauri::generate_context!())
+        .setup(|app| {
+            app.manage(replay::Player::spawn(app.handle()));
+            Ok(())
+        })
+        .manage(AppState(std::sync::Arc::new(std::sync::Mutex::new(Some(Settings { store, user_settings: Arc::clone(&user_settings), paths })))))
+        .invoke_handler(tauri::generate_handler![greet, applications, graph, app_stats, app_identity, controllers, rename_controller, preview_aliases, apply_aliases, get_settings, set_settings, rebuild_rollups, check_integrity, merge, export_jsonl, export_parquet, import_jsonl, import_evdev, applications_csv, graph_csv, app_stats_csv, share_bundle, open_bundle, stat_card, record_replay, load_replay, play_replay, pause_replay, seek_replay, replay_status, create_backup, list_backups, restore_backup])
+        .build(context)
         .expect("error while building tauri application")
         .run(move |app_handle, event| match event {
             // tauri::Event::Window(tauri::WindowEvent::Resized { size }) => {
@@ -640,6 +1174,11 @@ fn main() {
             tauri::RunEvent::ExitRequested { api, .. } => {
                 api.prevent_exit();
 
+                // we keep running in the tray, but this is the last chance if the os is the one closing us
+                if let Err(err) = writer_exit.flush() {
+                    log::error!("failed to flush on exit request: {err}");
+                }
+
                 // set the tray icon to invisible
                 let tray = app_handle.tray_handle();
                 let item = tray.get_item("toggle");
@@ -0,0 +1,297 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::identity::Identity;
+use crate::store::{self, Event, EventStore};
+use crate::{jsonl, paths};
+
+// events are compared against the local store this many at a time
+const CHUNK: usize = 10_000;
+// a sample of the conflicts, the report counts them all
+const EXAMPLES: usize = 20;
+
+/// Which app label wins when both sides have the same event under different apps.
+/// Either way "?" never wins over a real name.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Prefer {
+    Ours,
+    Theirs,
+}
+
+impl Prefer {
+    pub fn parse(prefer: Option<&str>) -> Result<Prefer, String> {
+        match prefer {
+            None | Some("ours") => Ok(Prefer::Ours),
+            Some("theirs") => Ok(Prefer::Theirs),
+            Some(other) => Err(format!("prefer has to be ours or theirs, not {other}")),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Conflict {
+    at: u128,
+    pad: String,
+    ours: String,
+    theirs: String,
+    kept: String,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct Report {
+    read: usize,
+    added: usize,
+    duplicates: usize,
+    conflicts: usize, // same event under another app
+    relabeled: usize, // conflicts where ours was changed to theirs
+    malformed: usize, // lines in an export that wont parse
+    controllers: usize, // theirs that we didnt have, ours keep their nicknames
+    identities: usize,  // apps whose exe and titles came over
+    skipped_hours: usize, // their downsampled history, only raw events can be told apart from ours
+    examples: Vec<Conflict>,
+}
+
+// at and pad are compared as is, the event as its bytes since it holds floats
+type Key = (u128, String, Vec<u8>);
+
+fn key(event: &Event) -> Key {
+    (event.at, event.pad.clone(), bincode::serialize(&event.event).unwrap())
+}
+
+// None keeps ours
+fn resolve(ours: &str, theirs: &str, prefer: Prefer) -> Option<String> {
+    if ours == theirs || theirs == "?" {
+        return None;
+    }
+
+    if ours == "?" || prefer == Prefer::Theirs {
+        return Some(theirs.to_string());
+    }
+
+    None
+}
+
+struct Merge<'a> {
+    store: &'a dyn EventStore,
+    prefer: Prefer,
+    chunk: Vec<Event>,
+    apps: HashSet<String>, // every app read, to bring over what is known about them
+    report: Report,
+}
+
+impl Merge<'_> {
+    fn push(&mut self, event: Event) -> Result<(), String> {
+        self.report.read += 1;
+        if !self.apps.contains(&event.app) {
+            self.apps.insert(event.app.clone());
+        }
+        self.chunk.push(event);
+        if self.chunk.len() >= CHUNK {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // compares the chunk to what we have over the same time span, then adds what is new
+    fn flush(&mut self) -> Result<(), String> {
+        let chunk = std::mem::take(&mut self.chunk);
+        let (Some(start), Some(end)) = (chunk.iter().map(|e| e.at).min(), chunk.iter().map(|e| e.at).max()) else {
+            return Ok(());
+        };
+
+        let mut ours = HashMap::<Key, String>::new();
+        self.store.scan(start, end + 1, &mut |event| {
+            ours.insert(key(&event), event.app);
+            true
+        })?;
+
+        let mut added = Vec::new();
+        let mut relabel = HashMap::<Key, String>::new();
+        for event in chunk {
+            let key = key(&event);
+            let Some(app) = ours.get(&key) else {
+                // a repeat inside the source is a duplicate as well
+                ours.insert(key, event.app.clone());
+                added.push(event);
+                continue;
+            };
+
+            self.report.duplicates += 1;
+            if *app == event.app {
+                continue;
+            }
+
+            self.report.conflicts += 1;
+            let kept = resolve(app, &event.app, self.prefer);
+            if self.report.examples.len() < EXAMPLES {
+                self.report.examples.push(Conflict {
+                    at: event.at,
+                    pad: event.pad.clone(),
+                    ours: app.clone(),
+                    theirs: event.app.clone(),
+                    kept: kept.clone().unwrap_or_else(|| app.clone()),
+                });
+            }
+
+            if let Some(theirs) = kept {
+                relabel.insert(key, theirs);
+            }
+        }
+
+        if !relabel.is_empty() {
+            self.report.relabeled += self.store.relabel(start, end + 1, &mut |event| relabel.get(&key(event)).cloned())?;
+        }
+
+        self.report.added += added.len();
+        self.store.append(&added)
+    }
+
+    fn db(&mut self, path: &Path) -> Result<(), String> {
+        let theirs = store::rocks::RocksStore::open(&path.to_string_lossy())?;
+
+        let mut failed = None;
+        theirs.scan(0, u128::MAX, &mut |event| match self.push(event) {
+            Ok(()) => true,
+            Err(err) => {
+                failed = Some(err);
+                false
+            }
+        })?;
+
+        failed.map_or(Ok(()), Err)?;
+
+        let ours = self.store.controllers()?;
+        for controller in theirs.controllers()? {
+            if !ours.iter().any(|ours| ours.id == controller.id) {
+                self.store.save_controller(&controller)?;
+                self.report.controllers += 1;
+            }
+        }
+
+        // oldest title first, so theirs end up in the same order they were seen
+        for app in &self.apps {
+            let Some(known) = theirs.known(app)? else {
+                continue;
+            };
+
+            for title in known.titles {
+                self.store.identify(app, &Identity { title, exe: known.exe.clone(), pid: known.pid })?;
+            }
+            self.report.identities += 1;
+        }
+
+        self.report.skipped_hours = theirs.downsampled()?;
+        if self.report.skipped_hours > 0 {
+            log::warn!("left out {} downsampled hours from {}, only raw events are merged", self.report.skipped_hours, path.display());
+        }
+
+        Ok(())
+    }
+}
+
+/// Adds every event from `path` that `store` doesnt have yet.
+/// `path` is either another coca-rocks.db, which is copied first and never touched, or a jsonl export.
+/// From a db the controllers and app identities come too, its downsampled history is counted in the report but left out.
+pub fn merge(store: &dyn EventStore, path: &str, prefer: Prefer) -> Result<Report, String> {
+    let mut merge = Merge { store, prefer, chunk: Vec::with_capacity(CHUNK), apps: HashSet::new(), report: Report::default() };
+
+    if Path::new(path).is_dir() {
+        // opening a db migrates it, so work on a copy in case it is older than us
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_secs();
+        let scratch = std::env::temp_dir().join(format!("coca-merge-{secs}"));
+        let copy = scratch.join("coca-rocks.db");
+        paths::copy(Path::new(path), &copy).map_err(|err| format!("failed to copy {path}: {err}"))?;
+
+        let result = merge.db(&copy);
+        if let Err(err) = std::fs::remove_dir_all(&scratch) {
+            log::warn!("failed to clean up {}: {err}", scratch.display());
+        }
+        result?;
+    } else {
+        let mut malformed = 0;
+        jsonl::read(path, &mut |n, line| match line.and_then(|line| line.check().map(|_| line)) {
+            Ok(line) => merge.push(line.into_event("?", "?")),
+            Err(err) => {
+                log::warn!("{path}:{n}: {err}");
+                malformed += 1;
+                Ok(())
+            }
+        })?;
+        merge.report.malformed = malformed;
+    }
+
+    merge.flush()?;
+    Ok(merge.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::store::rocks::RocksStore;
+    use crate::store::tests::{event, Scratch};
+
+    const AT: u128 = 1_700_000_000_000;
+
+    fn connected(at: u128, app: &str) -> Event {
+        event(at, app, gilrs::EventType::Connected)
+    }
+
+    fn apps(store: &dyn EventStore) -> Vec<(u128, String)> {
+        let mut apps = Vec::new();
+        store.scan(0, u128::MAX, &mut |event| {
+            apps.push((event.at, event.app));
+            true
+        }).unwrap();
+        apps
+    }
+
+    #[test]
+    fn merging_skips_what_we_have() {
+        let scratch = Scratch::new("merge");
+        let path = scratch.path("coca-rocks.db");
+        RocksStore::open(&path).unwrap().append(&[
+            connected(AT, "game"),       // the same as ours
+            connected(AT + 1, "theirs"), // ours doesnt know the app
+            connected(AT + 2, "theirs"), // ours has another app
+            connected(AT + 3, "new"),
+            connected(AT + 3, "new"), // a repeat in theirs
+        ]).unwrap();
+
+        let ours = MemoryStore::default();
+        ours.append(&[connected(AT, "game"), connected(AT + 1, "?"), connected(AT + 2, "ours")]).unwrap();
+
+        let report = merge(&ours, &path, Prefer::Ours).unwrap();
+        assert_eq!((report.read, report.added, report.duplicates), (5, 1, 4));
+        assert_eq!((report.conflicts, report.relabeled), (2, 1));
+        assert_eq!(apps(&ours), vec![
+            (AT + 3, "new".to_string()),
+            (AT + 2, "ours".to_string()),
+            (AT + 1, "theirs".to_string()),
+            (AT, "game".to_string()),
+        ]);
+
+        // again changes nothing
+        let report = merge(&ours, &path, Prefer::Ours).unwrap();
+        assert_eq!((report.added, report.relabeled), (0, 0));
+        assert_eq!(apps(&ours).len(), 4);
+    }
+
+    #[test]
+    fn theirs_wins_when_preferred() {
+        let scratch = Scratch::new("merge-theirs");
+        let path = scratch.path("coca-rocks.db");
+        RocksStore::open(&path).unwrap().append(&[connected(AT, "theirs")]).unwrap();
+
+        let ours = MemoryStore::default();
+        ours.append(&[connected(AT, "ours")]).unwrap();
+
+        let report = merge(&ours, &path, Prefer::Theirs).unwrap();
+        assert_eq!(report.relabeled, 1);
+        assert_eq!(apps(&ours), vec![(AT, "theirs".to_string())]);
+    }
+}
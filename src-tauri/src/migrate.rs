@@ -0,0 +1,388 @@
+use rocksdb::{WriteBatch, DB};
+use serde::Deserialize;
+
+use crate::store::BATCH_SIZE;
+use crate::{dict, integrity, Rock, DB_VERSION};
+
+// holds the version the data was last migrated to
+// the keys carry it too, but an empty or half migrated db cant tell you much
+pub const META_CF: &str = "meta";
+const VERSION_KEY: &[u8] = b"version";
+
+pub struct Migration {
+    pub from: u8,
+    pub to: u8,
+    pub name: &'static str,
+    pub run: fn(&mut Migrator) -> Result<(), String>,
+}
+
+// every step from the first db version to the current one, in order
+// a new DB_VERSION needs a new entry here or old dbs will stop loading
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { from: 1, to: 2, name: "time sortable keys", run: v1_to_v2 },
+    Migration { from: 2, to: 3, name: "interned app and pad names", run: v2_to_v3 },
+];
+
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub name: &'static str,
+    pub from: u8,
+    pub to: u8,
+    pub done: usize,
+    pub total: u64, // estimate from rocks, can be off
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub from: u8,
+    pub to: u8,
+    pub rewritten: usize,
+    pub quarantined: usize, // rows a step couldnt read
+    pub unchecked: Vec<&'static str>, // steps a dry run couldnt try, see run
+}
+
+/// Handed to each migration step, buffers the rewrites and reports progress.
+/// In a dry run nothing is written, but the counts for the step are still real.
+pub struct Migrator<'a> {
+    pub db: &'a DB,
+    pub dict: dict::Dict, // wont write in a dry run either
+    dry_run: bool,
+    batch: WriteBatch,
+    progress: Progress,
+    quarantined: usize,
+    on_progress: &'a dyn Fn(&Progress),
+}
+
+impl<'a> Migrator<'a> {
+    /// Replaces the row at `old_key` with `value` at `new_key`.
+    pub fn rewrite(&mut self, old_key: &[u8], new_key: &[u8], value: &[u8]) -> Result<(), String> {
+        if !self.dry_run {
+            if old_key != new_key {
+                self.batch.delete(old_key);
+            }
+            self.batch.put(new_key, value);
+        }
+
+        self.progress.done += 1;
+        if self.progress.done % BATCH_SIZE == 0 {
+            self.flush()?;
+            (self.on_progress)(&self.progress);
+        }
+
+        Ok(())
+    }
+
+    /// Moves a row the step cant read into quarantine.
+    /// Left where it is it would keep the db at the old version, every later run skipping it again.
+    pub fn quarantine(&mut self, key: &[u8], value: &[u8], reason: String) -> Result<(), String> {
+        log::warn!("quarantining a row {} cant read: {reason}", self.progress.name);
+        self.quarantined += 1;
+        if self.dry_run {
+            return Ok(());
+        }
+
+        integrity::quarantine(self.db, &mut self.batch, key, value.to_vec(), reason)
+    }
+
+    /// Drops a flag from the meta cf, so whatever set it gets built again on startup.
+    pub fn forget(&mut self, key: &[u8]) -> Result<(), String> {
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let meta = self.db.cf_handle(META_CF).ok_or("missing meta column family")?;
+        self.db.delete_cf(meta, key).map_err(|err| err.to_string())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        self.db.write(std::mem::take(&mut self.batch)).map_err(|err| err.to_string())
+    }
+}
+
+// v1 was [1, nonce, at (native endian)], which sorted by nonce first
+// rewrite every v1 row to the v2 layout, the old nonce keeps the rows unique
+fn v1_to_v2(m: &mut Migrator) -> Result<(), String> {
+    let db = m.db;
+
+    // v1 rows all start with 1, so start there and stop at the first row that isnt
+    for row in db.iterator(rocksdb::IteratorMode::From(&[1], rocksdb::Direction::Forward)) {
+        let (key, value) = row.map_err(|err| err.to_string())?;
+        if key[0] != 1 {
+            break;
+        }
+
+        if key.len() != 18 {
+            m.quarantine(&key, &value, format!("key length: {} bytes, not 18", key.len()))?;
+            continue;
+        }
+
+        let at = u128::from_ne_bytes(key[2..].try_into().unwrap());
+
+        // [2, at (big endian), nonce]
+        let mut pk = [0u8; 19];
+        pk[0] = 2;
+        pk[1..17].copy_from_slice(&at.to_be_bytes());
+        pk[17..].copy_from_slice(&(key[1] as u16).to_be_bytes());
+        m.rewrite(&key, &pk, &value)?;
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct RockV2 {
+    at: u128,
+    pad: String,
+    app: String,
+    event: gilrs::EventType,
+}
+
+// v2 rocks carried the app and pad names, v3 only has their ids
+// the key layout is the same, only the version changes
+// the rollups and app index are keyed by name too, so they get built again
+fn v2_to_v3(m: &mut Migrator) -> Result<(), String> {
+    let db = m.db;
+
+    for row in db.iterator(rocksdb::IteratorMode::From(&[2], rocksdb::Direction::Forward)) {
+        let (key, value) = row.map_err(|err| err.to_string())?;
+        if key[0] != 2 {
+            break;
+        }
+
+        let old: RockV2 = match bincode::deserialize(&value) {
+            Ok(old) => old,
+            Err(err) => {
+                m.quarantine(&key, &value, format!("decode: {err}"))?;
+                continue;
+            }
+        };
+
+        let rock = Rock {
+            at: old.at,
+            pad: m.dict.intern(db, dict::Kind::Pad, &old.pad)?,
+            app: m.dict.intern(db, dict::Kind::App, &old.app)?,
+            event: old.event,
+        };
+
+        let mut pk = key.to_vec();
+        pk[0] = 3;
+        m.rewrite(&key, &pk, &bincode::serialize(&rock).unwrap())?;
+    }
+
+    m.forget(crate::rollup::BUILT_KEY)?;
+    m.forget(crate::index::BUILT_KEY)?;
+
+    Ok(())
+}
+
+fn set_version(db: &DB, version: u8) -> Result<(), String> {
+    let meta = db.cf_handle(META_CF).ok_or("missing meta column family")?;
+    db.put_cf(meta, VERSION_KEY, [version]).map_err(|err| err.to_string())
+}
+
+/// Forgets the version the db was migrated to, so the next run goes by the oldest row and migrates from there.
+/// For rows an older coca skipped when it migrated, before unreadable ones were quarantined.
+pub fn reset(db: &DB) -> Result<(), String> {
+    let meta = db.cf_handle(META_CF).ok_or("missing meta column family")?;
+    db.delete_cf(meta, VERSION_KEY).map_err(|err| err.to_string())
+}
+
+/// Works out which version the data is in.
+/// Trusts the meta cf first, then the first key, and an empty db is always current.
+pub fn version(db: &DB) -> Result<u8, String> {
+    let meta = db.cf_handle(META_CF).ok_or("missing meta column family")?;
+    if let Some(version) = db.get_cf(meta, VERSION_KEY).map_err(|err| err.to_string())? {
+        return Ok(version[0]);
+    }
+
+    // the lowest version sorts first, so that is the one that still needs work
+    match db.iterator(rocksdb::IteratorMode::Start).next() {
+        Some(row) => {
+            let (key, _) = row.map_err(|err| err.to_string())?;
+            Ok(key[0])
+        }
+        None => Ok(DB_VERSION),
+    }
+}
+
+/// Brings the db up to DB_VERSION, one step at a time.
+/// A checkpoint of the untouched db is made next to it first, unless this is a dry run.
+/// A dry run stops after the first step, the next one would read rows the first never wrote.
+pub fn run(db: &DB, dry_run: bool, on_progress: &dyn Fn(&Progress)) -> Result<Report, String> {
+    let from = version(db)?;
+    let mut report = Report { from, to: from, ..Default::default() };
+
+    if from == DB_VERSION {
+        if !dry_run {
+            set_version(db, DB_VERSION)?;
+        }
+        return Ok(report);
+    }
+
+    if from > DB_VERSION {
+        return Err(format!("Database version {from} is newer than this build ({DB_VERSION})"));
+    }
+
+    // make sure there is a path all the way up before touching anything
+    let mut steps = Vec::new();
+    let mut at = from;
+    while at < DB_VERSION {
+        let step = MIGRATIONS.iter().find(|m| m.from == at).ok_or_else(|| format!("no migration from version {at}"))?;
+        steps.push(step);
+        at = step.to;
+    }
+
+    if !dry_run {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).expect("Time went backwards").as_secs();
+        let path = std::path::PathBuf::from(format!("{}.pre-v{from}-{now}", db.path().display()));
+        rocksdb::checkpoint::Checkpoint::new(db)
+            .and_then(|checkpoint| checkpoint.create_checkpoint(&path))
+            .map_err(|err| format!("failed to checkpoint before migrating: {err}"))?;
+        log::info!("checkpoint saved to {}", path.display());
+    }
+
+    if dry_run && steps.len() > 1 {
+        report.unchecked = steps.split_off(1).iter().map(|step| step.name).collect();
+    }
+
+    let total = db.property_int_value("rocksdb.estimate-num-keys").ok().flatten().unwrap_or(0);
+    for step in steps {
+        log::info!("migrating v{} -> v{}: {}{}", step.from, step.to, step.name, if dry_run { " (dry run)" } else { "" });
+
+        let mut m = Migrator {
+            db,
+            dict: if dry_run { dict::Dict::dry_run() } else { dict::Dict::default() },
+            dry_run,
+            batch: WriteBatch::default(),
+            progress: Progress { name: step.name, from: step.from, to: step.to, done: 0, total },
+            quarantined: 0,
+            on_progress,
+        };
+
+        (step.run)(&mut m)?;
+        m.flush()?;
+        on_progress(&m.progress);
+
+        // only bump the version once the whole step made it in
+        // a crash half way just runs the step again on the rows that are left
+        if !dry_run {
+            set_version(db, step.to)?;
+        }
+
+        report.rewritten += m.progress.done;
+        report.quarantined += m.quarantined;
+        report.to = step.to;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Serialize;
+
+    use super::*;
+    use crate::store::rocks::RocksStore;
+    use crate::store::tests::Scratch;
+    use crate::store::EventStore;
+    use crate::{decode_key, encode_key, KEY_LEN};
+
+    // an hour on 2023-11-14, anything before 2000 doesnt pass the integrity check
+    const AT: u128 = 1_700_002_800_000;
+
+    // v1 and v2 values are the same, only the key changed
+    #[derive(Serialize)]
+    struct RockV1 {
+        at: u128,
+        pad: String,
+        app: String,
+        event: gilrs::EventType,
+    }
+
+    fn v1(db: &DB, nonce: u8, at: u128, app: &str) {
+        let mut key = vec![1, nonce];
+        key.extend_from_slice(&at.to_ne_bytes());
+        let rock = RockV1 { at, pad: "pad".to_string(), app: app.to_string(), event: gilrs::EventType::Connected };
+        db.put(key, bincode::serialize(&rock).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn keys_sort_by_time() {
+        assert!(encode_key(AT, u16::MAX) < encode_key(AT + 1, 0));
+        assert!(encode_key(255, 0) < encode_key(256, 0));
+        assert_eq!(decode_key(&encode_key(AT, 7)), Ok(AT));
+
+        assert!(decode_key(&[DB_VERSION - 1; KEY_LEN]).is_err());
+        assert!(decode_key(&encode_key(AT, 7)[1..]).is_err());
+    }
+
+    #[test]
+    fn migrates_v1_to_current() {
+        let scratch = Scratch::new("migrate");
+        let path = scratch.path("coca-rocks.db");
+        {
+            let db = store_db(&path);
+            // v1 sorted by nonce, so the newer event is first here
+            v1(&db, 0, AT + 1, "newer");
+            v1(&db, 1, AT, "older");
+
+            let report = run(&db, false, &|_| {}).unwrap();
+            assert_eq!((report.from, report.to, report.rewritten), (1, DB_VERSION, 4));
+            assert!(report.unchecked.is_empty());
+            assert_eq!(version(&db), Ok(DB_VERSION));
+        }
+
+        let store = RocksStore::open(&path).unwrap();
+        let mut events = Vec::new();
+        store.scan(0, u128::MAX, &mut |event| {
+            events.push((event.at, event.app));
+            true
+        }).unwrap();
+        assert_eq!(events, vec![(AT + 1, "newer".to_string()), (AT, "older".to_string())]);
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() {
+        let scratch = Scratch::new("dry-run");
+        let db = store_db(&scratch.path("coca-rocks.db"));
+        v1(&db, 0, AT, "app");
+        v1(&db, 1, AT + 1, "app");
+
+        let report = run(&db, true, &|_| {}).unwrap();
+        assert_eq!((report.from, report.to, report.rewritten), (1, 2, 2));
+        assert_eq!(report.unchecked, vec!["interned app and pad names"]);
+
+        assert_eq!(version(&db), Ok(1));
+        assert_eq!(db.iterator(rocksdb::IteratorMode::Start).count(), 2);
+    }
+
+    #[test]
+    fn unreadable_rows_are_quarantined() {
+        let scratch = Scratch::new("migrate-quarantine");
+        let db = store_db(&scratch.path("coca-rocks.db"));
+        v1(&db, 0, AT, "app");
+        db.put([1, 1, 2, 3], b"short key").unwrap();
+        let mut v2 = encode_key(AT + 1, 0);
+        v2[0] = 2;
+        db.put(v2, b"not a rock").unwrap();
+
+        let report = run(&db, false, &|_| {}).unwrap();
+        assert_eq!((report.to, report.quarantined), (DB_VERSION, 2));
+
+        // nothing is left at an old version for --migrate-again to trip on
+        let rows: Vec<_> = db.iterator(rocksdb::IteratorMode::Start).map(|row| row.unwrap().0).collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0][0], DB_VERSION);
+
+        let quarantine = db.cf_handle(integrity::QUARANTINE_CF).unwrap();
+        assert_eq!(db.iterator_cf(quarantine, rocksdb::IteratorMode::Start).count(), 2);
+    }
+
+    fn store_db(path: &str) -> DB {
+        crate::store::rocks::open_db(path).unwrap()
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::backup::Backups;
+use crate::{cli, UserSettings};
+
+// where things go when neither a flag nor an env var says otherwise
+// flags win over env vars, e.g. `coca --data-dir ~/coca` or `COCA_DATA_DIR=~/coca coca`
+const DATA_FLAG: &str = "--data-dir";
+const DATA_ENV: &str = "COCA_DATA_DIR";
+const CONFIG_FLAG: &str = "--config-dir";
+const CONFIG_ENV: &str = "COCA_CONFIG_DIR";
+
+const ROCKS: &str = "coca-rocks.db";
+const SQLITE: &str = "coca.sqlite";
+const BACKUPS: &str = "coca-backups";
+const SETTINGS: &str = "settings.json";
+
+/// Every file coca keeps, resolved once at startup.
+#[derive(Clone)]
+pub struct Paths {
+    data: PathBuf,
+    config: PathBuf,
+}
+
+fn pick(flag_name: &str, env: &str, default: Option<PathBuf>) -> Result<PathBuf, String> {
+    cli::value(flag_name).map(PathBuf::from)
+        .or_else(|| std::env::var_os(env).filter(|dir| !dir.is_empty()).map(PathBuf::from))
+        .or(default)
+        .ok_or_else(|| format!("no {flag_name} given and the platform has no default, set {env}"))
+}
+
+// the stores take &str, anything that isnt utf-8 just gets replaced
+fn join(dir: &Path, name: &str) -> String {
+    dir.join(name).to_string_lossy().into_owned()
+}
+
+// the working directory can be anyone's project, so only take what is plainly ours
+fn ours(name: &str, path: &Path) -> bool {
+    match name {
+        ROCKS => path.join("CURRENT").is_file(),
+        // every backup engine dir has this, even with no backups in it
+        BACKUPS => path.join("meta").is_dir(),
+        SQLITE => {
+            let mut header = [0u8; 16];
+            std::fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)).is_ok() && &header == b"SQLite format 3\0"
+        }
+        SETTINGS => std::fs::read_to_string(path).is_ok_and(|json| serde_json::from_str::<UserSettings>(&json).is_ok()),
+        _ => false,
+    }
+}
+
+fn remove(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+/// Copies a file, or a dir and everything in it.
+pub fn copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    if !from.is_dir() {
+        return std::fs::copy(from, to).map(|_| ());
+    }
+
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        copy(&entry.path(), &to.join(entry.file_name()))?;
+    }
+
+    Ok(())
+}
+
+impl Paths {
+    /// The app data and config dirs from tauri (XDG on linux), unless overridden.
+    /// Makes both dirs if they dont exist yet.
+    pub fn resolve(config: &tauri::Config) -> Result<Paths, String> {
+        let paths = Paths {
+            data: pick(DATA_FLAG, DATA_ENV, tauri::api::path::app_data_dir(config))?,
+            config: pick(CONFIG_FLAG, CONFIG_ENV, tauri::api::path::app_config_dir(config))?,
+        };
+
+        for dir in [&paths.data, &paths.config] {
+            std::fs::create_dir_all(dir).map_err(|err| format!("failed to make {}: {err}", dir.display()))?;
+        }
+
+        Ok(paths)
+    }
+
+    pub fn rocks(&self) -> String {
+        join(&self.data, ROCKS)
+    }
+
+    pub fn sqlite(&self) -> String {
+        join(&self.data, SQLITE)
+    }
+
+    /// The dir from the backup settings, or coca-backups next to the db.
+    pub fn backups(&self, backups: &Backups) -> String {
+        match backups.dir.as_deref().map(str::trim).filter(|dir| !dir.is_empty()) {
+            Some(dir) => dir.to_string(),
+            None => join(&self.data, BACKUPS),
+        }
+    }
+
+    pub fn logs(&self) -> PathBuf {
+        self.data.join("logs")
+    }
+
+    pub fn settings(&self) -> String {
+        join(&self.config, SETTINGS)
+    }
+
+    /// Copies files left in the working directory by older versions to where they go now.
+    /// Only copies a file if it is really coca's and there isnt one in the new place already, so this only ever happens once.
+    /// The originals stay where they are, deleting them is up to whoever left them there.
+    /// Runs before the logger is up, so it hands back what it did instead of logging it.
+    pub fn adopt(&self) -> Vec<String> {
+        let Ok(cwd) = std::env::current_dir() else {
+            return Vec::new();
+        };
+
+        let moves = [
+            (ROCKS, &self.data),
+            (SQLITE, &self.data),
+            (BACKUPS, &self.data),
+            (SETTINGS, &self.config),
+        ];
+
+        let mut notes = Vec::new();
+        for (name, dir) in moves {
+            let from = cwd.join(name);
+            let to = dir.join(name);
+
+            // started from the data dir, or pointed at the working directory
+            let same = match (from.canonicalize(), dir.canonicalize()) {
+                (Ok(from), Ok(dir)) => from.parent() == Some(dir.as_path()),
+                _ => false,
+            };
+
+            if same || !from.exists() || to.exists() {
+                continue;
+            }
+
+            if !ours(name, &from) {
+                notes.push(format!("left {} alone, it doesnt look like coca's", from.display()));
+                continue;
+            }
+
+            // half a copy would look like it was already adopted next time
+            match copy(&from, &to) {
+                Ok(()) => notes.push(format!("copied {} to {}, the original can be deleted", from.display(), to.display())),
+                Err(err) => {
+                    let _ = remove(&to);
+                    notes.push(format!("failed to copy {} to {}: {err}", from.display(), to.display()));
+                }
+            }
+        }
+
+        notes
+    }
+}
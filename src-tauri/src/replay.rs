@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::store::EventStore;
+
+// a .coca-replay is MAGIC, the version, then a bincode Replay
+// same rules as bundles, a layout change is a new version and open keeps reading the old ones
+const MAGIC: &[u8; 4] = b"COCR";
+const REPLAY_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Pad {
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Frame {
+    offset: u64, // ms since the replay started
+    pad: u16,    // index into pads
+    event: gilrs::EventType,
+}
+
+/// A stretch of events with their times relative to the start, so it can be played back later.
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    from: u128, // ms since the unix epoch, what offset 0 is
+    to: u128,
+    app: Option<String>, // only this apps events went in
+    coca: String,
+    platform: String, // event codes only mean something on the os they came from
+    pads: Vec<Pad>,
+    frames: Vec<Frame>, // by offset
+}
+
+impl Replay {
+    pub fn events(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn duration(&self) -> u64 {
+        (self.to - self.from) as u64
+    }
+
+    // a replay can come from anywhere, the player indexes and subtracts with all of this so it has to hold
+    fn check(&self) -> Result<(), String> {
+        if self.from > self.to {
+            return Err("ends before it starts".to_string());
+        }
+
+        let mut last = 0;
+        for frame in &self.frames {
+            if frame.pad as usize >= self.pads.len() {
+                return Err(format!("has an event from pad {} of {}", frame.pad, self.pads.len()));
+            }
+            if frame.offset < last || frame.offset > self.duration() {
+                return Err(format!("has an event at {} out of order or past the end", frame.offset));
+            }
+            last = frame.offset;
+        }
+
+        Ok(())
+    }
+}
+
+/// Records `start <= at < end`, only `app` if given.
+pub fn record(store: &dyn EventStore, start: u128, end: u128, app: Option<&str>) -> Result<Replay, String> {
+    if end <= start {
+        return Err("a replay needs to end after it starts".to_string());
+    }
+
+    let mut pads = Vec::<Pad>::new();
+    let mut frames = Vec::new();
+    let mut f = |event: crate::store::Event| {
+        let pad = match pads.iter().position(|pad| pad.name == event.pad) {
+            Some(pad) => pad,
+            None => {
+                pads.push(Pad { name: event.pad });
+                pads.len() - 1
+            }
+        };
+        frames.push(Frame { offset: (event.at - start) as u64, pad: pad as u16, event: event.event });
+        true
+    };
+
+    match app {
+        Some(app) => store.scan_app(app, start, end, &mut f)?,
+        None => store.scan(start, end, &mut f)?,
+    }
+    frames.sort_by_key(|frame| frame.offset);
+
+    Ok(Replay {
+        from: start,
+        to: end,
+        app: app.map(str::to_string),
+        coca: env!("CARGO_PKG_VERSION").to_string(),
+        platform: std::env::consts::OS.to_string(),
+        pads,
+        frames,
+    })
+}
+
+pub fn save(replay: &Replay, path: &str) -> Result<(), String> {
+    let mut file = std::fs::File::create(path).map_err(|err| format!("failed to create {path}: {err}"))?;
+    file.write_all(MAGIC).and_then(|_| file.write_all(&[REPLAY_VERSION])).map_err(|err| format!("failed to write {path}: {err}"))?;
+    bincode::serialize_into(std::io::BufWriter::new(file), replay).map_err(|err| format!("failed to write {path}: {err}"))
+}
+
+pub fn open(path: &str) -> Result<Replay, String> {
+    let mut file = std::fs::File::open(path).map_err(|err| format!("failed to open {path}: {err}"))?;
+
+    let mut header = [0u8; 5];
+    file.read_exact(&mut header).map_err(|_| format!("{path} is too short to be a replay"))?;
+    if &header[..4] != MAGIC {
+        return Err(format!("{path} isnt a coca replay"));
+    }
+
+    if header[4] != REPLAY_VERSION {
+        return Err(format!("{path} is replay version {}, this coca reads up to {REPLAY_VERSION}", header[4]));
+    }
+
+    // limited to the file size, see bundle::open
+    let limit = file.metadata().map_err(|err| format!("failed to read {path}: {err}"))?.len();
+    let replay: Replay = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+        .deserialize_from(std::io::BufReader::new(file))
+        .map_err(|err| format!("{path} is damaged: {err}"))?;
+
+    replay.check().map_err(|err| format!("{path} is damaged, it {err}"))?;
+    Ok(replay)
+}
+
+/// What the frontend gets for every event played, as "replay-event".
+#[derive(Serialize, Clone)]
+struct Played<'a> {
+    offset: u64,
+    pad: &'a str,
+    event: gilrs::EventType,
+}
+
+/// Sent as "replay-seek", the last value of every button and axis up to the new position,
+/// so whatever is drawing the controller doesnt have stuck buttons after jumping around.
+#[derive(Serialize, Clone)]
+struct Seeked<'a> {
+    position: u64,
+    state: Vec<Played<'a>>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Status {
+    loaded: bool,
+    duration: u64, // ms
+    position: u64,
+    playing: bool,
+    speed: f64,
+}
+
+#[derive(Default)]
+struct Playback {
+    replay: Option<Arc<Replay>>,
+    next: usize,            // the next frame to send
+    position: u64,          // ms into the replay, as of since
+    since: Option<Instant>, // set while playing
+    speed: f64,
+}
+
+impl Playback {
+    fn now(&self) -> u64 {
+        match self.since {
+            Some(since) => self.position + (since.elapsed().as_millis() as f64 * self.speed) as u64,
+            None => self.position,
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            loaded: self.replay.is_some(),
+            duration: self.replay.as_ref().map_or(0, |replay| replay.duration()),
+            position: self.now(),
+            playing: self.since.is_some(),
+            speed: self.speed,
+        }
+    }
+}
+
+/// Plays a loaded replay back to the frontend as tauri events, on its own thread.
+pub struct Player {
+    playback: Mutex<Playback>,
+    changed: Condvar,
+}
+
+impl Player {
+    pub fn spawn(app: tauri::AppHandle) -> Arc<Player> {
+        let player = Arc::new(Player { playback: Mutex::new(Playback { speed: 1.0, ..Default::default() }), changed: Condvar::new() });
+
+        let thread = Arc::clone(&player);
+        std::thread::spawn(move || thread.run(app));
+
+        player
+    }
+
+    fn run(&self, app: tauri::AppHandle) {
+        let mut playback = self.playback.lock().unwrap();
+        loop {
+            let replay = match (&playback.replay, playback.since) {
+                (Some(replay), Some(_)) => Arc::clone(replay),
+                _ => {
+                    playback = self.changed.wait(playback).unwrap();
+                    continue;
+                }
+            };
+
+            let now = playback.now();
+            let due = replay.frames[playback.next..].partition_point(|frame| frame.offset <= now);
+            for frame in &replay.frames[playback.next..playback.next + due] {
+                let _ = app.emit_all("replay-event", Played { offset: frame.offset, pad: &replay.pads[frame.pad as usize].name, event: frame.event });
+            }
+            playback.next += due;
+
+            let wait = match replay.frames.get(playback.next) {
+                Some(frame) => Duration::from_millis(((frame.offset - now) as f64 / playback.speed).ceil() as u64),
+                None if now >= replay.duration() => {
+                    playback.position = replay.duration();
+                    playback.since = None;
+                    let _ = app.emit_all("replay-end", playback.status());
+                    continue;
+                }
+                None => Duration::from_millis(((replay.duration() - now) as f64 / playback.speed).ceil() as u64),
+            };
+
+            // woken early by play, pause, seek or load, which is fine, the loop works it out again
+            playback = self.changed.wait_timeout(playback, wait).unwrap().0;
+        }
+    }
+
+    pub fn load(&self, replay: Replay) -> Status {
+        let mut playback = self.playback.lock().unwrap();
+        *playback = Playback { replay: Some(Arc::new(replay)), speed: playback.speed, ..Default::default() };
+        self.changed.notify_all();
+        playback.status()
+    }
+
+    /// Plays from where it is, or the start if it had finished, at `speed` times real time.
+    pub fn play(&self, speed: Option<f64>) -> Result<Status, String> {
+        let mut playback = self.playback.lock().unwrap();
+        let duration = playback.replay.as_ref().ok_or("no replay is loaded")?.duration();
+
+        // folded in first, since gets reset below and whatever played since then would be lost
+        playback.position = playback.now();
+        if let Some(speed) = speed {
+            if !(speed > 0.0 && speed.is_finite()) {
+                return Err(format!("{speed} isnt a speed a replay can play at"));
+            }
+            playback.speed = speed;
+        }
+
+        if playback.since.is_none() && playback.position >= duration {
+            playback.position = 0;
+            playback.next = 0;
+        }
+        playback.since = Some(Instant::now());
+
+        self.changed.notify_all();
+        Ok(playback.status())
+    }
+
+    pub fn pause(&self) -> Status {
+        let mut playback = self.playback.lock().unwrap();
+        playback.position = playback.now();
+        playback.since = None;
+        self.changed.notify_all();
+        playback.status()
+    }
+
+    /// Jumps to `position` ms in, keeps playing if it was.
+    pub fn seek(&self, app: &tauri::AppHandle, position: u64) -> Result<Status, String> {
+        let mut playback = self.playback.lock().unwrap();
+        let replay = Arc::clone(playback.replay.as_ref().ok_or("no replay is loaded")?);
+
+        let position = position.min(replay.duration());
+        playback.position = position;
+        playback.next = replay.frames.partition_point(|frame| frame.offset < position);
+        if playback.since.is_some() {
+            playback.since = Some(Instant::now());
+        }
+
+        // later frames overwrite earlier ones, whats left is how everything was at position
+        let mut state = HashMap::new();
+        for frame in &replay.frames[..playback.next] {
+            let key = match frame.event {
+                gilrs::EventType::ButtonChanged(button, ..) => (frame.pad, Some(button), None),
+                gilrs::EventType::AxisChanged(axis, ..) => (frame.pad, None, Some(axis)),
+                _ => continue,
+            };
+            state.insert(key, frame);
+        }
+        let state = state.into_values()
+            .map(|frame| Played { offset: frame.offset, pad: &replay.pads[frame.pad as usize].name, event: frame.event })
+            .collect();
+        let _ = app.emit_all("replay-seek", Seeked { position, state });
+
+        self.changed.notify_all();
+        Ok(playback.status())
+    }
+
+    pub fn status(&self) -> Status {
+        self.playback.lock().unwrap().status()
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rocksdb::{WriteBatch, DB};
+use serde::{Deserialize, Serialize};
+
+use crate::store::{EventStore, Skipped, Summary, BATCH_SIZE};
+use crate::{index, integrity, rollup, UserSettings, DAY, HOUR};
+
+// raw rocks past the retention window get folded into one summary per hour
+// key: [hour start (big endian u64 ms), app id, pad id]
+// value: bincode Summary
+pub const DOWNSAMPLED_CF: &str = "downsampled";
+
+const RUN_EVERY: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Retention {
+    raw_days: u32, // 0 keeps everything raw
+}
+
+fn key(hour: u64, app: u32, pad: u32) -> [u8; 16] {
+    let mut key = [0u8; 16];
+    key[..8].copy_from_slice(&hour.to_be_bytes());
+    key[8..12].copy_from_slice(&app.to_be_bytes());
+    key[12..].copy_from_slice(&pad.to_be_bytes());
+    key
+}
+
+/// What a summary is for.
+pub struct Hour {
+    pub at: u64,
+    pub app: u32,
+    pub pad: u32,
+}
+
+fn decode_key(key: &[u8]) -> Option<Hour> {
+    if key.len() != 16 {
+        return None;
+    }
+
+    Some(Hour {
+        at: u64::from_be_bytes(key[..8].try_into().unwrap()),
+        app: u32::from_be_bytes(key[8..12].try_into().unwrap()),
+        pad: u32::from_be_bytes(key[12..].try_into().unwrap()),
+    })
+}
+
+fn decode(value: &[u8]) -> Result<Summary, String> {
+    bincode::deserialize(value).map_err(|err| err.to_string())
+}
+
+fn commit(db: &DB, mut batch: WriteBatch, summaries: HashMap<[u8; 16], Summary>) -> Result<(), String> {
+    let cf = db.cf_handle(DOWNSAMPLED_CF).ok_or("missing downsampled column family")?;
+
+    // read, add, write, the store holds its rewriting lock around everything that calls this
+    for (key, mut summary) in summaries {
+        if let Some(existing) = db.get_cf(cf, key).map_err(|err| err.to_string())? {
+            match decode(&existing) {
+                Ok(existing) => summary.merge(existing),
+                Err(err) => log::warn!("replacing summary that wont decode: {err}"),
+            }
+        }
+
+        batch.put_cf(cf, key, bincode::serialize(&summary).unwrap());
+    }
+
+    db.write(batch).map_err(|err| err.to_string())
+}
+
+/// Folds every raw rock from before `before` into hourly summaries and deletes it.
+/// Only whole hours are touched, so an hour is never half raw and half summarized.
+pub fn downsample(db: &DB, before: u128) -> Result<usize, String> {
+    let before = before - before % HOUR;
+
+    let mut folded = 0;
+    let mut batch = WriteBatch::default();
+    let mut summaries = HashMap::<[u8; 16], Summary>::new();
+    let mut skipped = Skipped::new("downsample");
+
+    for row in db.iterator(rocksdb::IteratorMode::Start) {
+        let (pk, value) = row.map_err(|err| err.to_string())?;
+        let rock = match integrity::check(&pk, &value) {
+            Ok(rock) => rock,
+            Err(bad) => {
+                skipped.add(bad);
+                continue;
+            }
+        };
+
+        let at = rock.at;
+        if at >= before {
+            break; // oldest first, so the rest are still in the window
+        }
+
+        let hour = (at - at % HOUR) as u64;
+        summaries.entry(key(hour, rock.app, rock.pad)).or_default().add(&rock.event);
+
+        // the rollups stay, they are already as small as they get
+        batch.delete(&pk);
+        index::remove(db, &mut batch, &pk, &rock);
+        folded += 1;
+
+        if folded % BATCH_SIZE == 0 {
+            commit(db, std::mem::take(&mut batch), std::mem::take(&mut summaries))?;
+        }
+    }
+
+    commit(db, batch, summaries)?;
+    Ok(folded)
+}
+
+/// Every summary for `app` from `start` on, with the pad it was for.
+/// The hour holding `start` is counted whole, like the rollups.
+pub fn since(db: &DB, app: u32, start: u128) -> Result<Vec<(u32, Summary)>, String> {
+    let cf = db.cf_handle(DOWNSAMPLED_CF).ok_or("missing downsampled column family")?;
+    let first = ((start - start % HOUR) as u64).to_be_bytes();
+
+    let mut summaries = Vec::new();
+    let mut skipped = Skipped::new("downsampled summaries");
+    for row in db.iterator_cf(cf, rocksdb::IteratorMode::From(&first, rocksdb::Direction::Forward)) {
+        let (key, value) = row.map_err(|err| err.to_string())?;
+        let Some(hour) = decode_key(&key).filter(|hour| hour.app == app) else {
+            continue;
+        };
+
+        match decode(&value) {
+            Ok(summary) => summaries.push((hour.pad, summary)),
+            Err(err) => skipped.add(err),
+        }
+    }
+
+    Ok(summaries)
+}
+
+/// Moves every summary whose app `f` gives another id to that app, added to any it already has for the hour,
+/// and takes its rollup counts with it. Returns how many events moved.
+pub fn relabel(db: &DB, f: &mut dyn FnMut(u32) -> Result<Option<u32>, String>) -> Result<usize, String> {
+    let cf = db.cf_handle(DOWNSAMPLED_CF).ok_or("missing downsampled column family")?;
+
+    let mut moved = 0;
+    let mut hours = 0;
+    let mut batch = WriteBatch::default();
+    let mut summaries = HashMap::<[u8; 16], Summary>::new();
+    let mut skipped = Skipped::new("downsampled relabel");
+    for row in db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+        let (old, value) = row.map_err(|err| err.to_string())?;
+        let (Some(hour), Ok(summary)) = (decode_key(&old), decode(&value)) else {
+            skipped.add("bad downsampled summary");
+            continue;
+        };
+
+        let Some(to) = f(hour.app)?.filter(|to| *to != hour.app) else {
+            continue;
+        };
+
+        moved += summary.kinds.values().sum::<u64>() as usize;
+        rollup::move_hour(db, &mut batch, hour.at, hour.app, hour.pad, to)?;
+        batch.delete_cf(cf, &old);
+        summaries.entry(key(hour.at, to, hour.pad)).or_default().merge(summary);
+        hours += 1;
+
+        if hours % BATCH_SIZE == 0 {
+            commit(db, std::mem::take(&mut batch), std::mem::take(&mut summaries))?;
+        }
+    }
+
+    commit(db, batch, summaries)?;
+    Ok(moved)
+}
+
+/// Calls `f` with every summary and what its for, oldest first.
+pub fn each(db: &DB, f: &mut dyn FnMut(Hour, Summary) -> Result<(), String>) -> Result<(), String> {
+    let cf = db.cf_handle(DOWNSAMPLED_CF).ok_or("missing downsampled column family")?;
+
+    let mut skipped = Skipped::new("downsampled summaries");
+    for row in db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+        let (key, value) = row.map_err(|err| err.to_string())?;
+        let Some(hour) = decode_key(&key) else {
+            skipped.add("bad downsampled key");
+            continue;
+        };
+
+        match decode(&value) {
+            Ok(summary) => f(hour, summary)?,
+            Err(err) => skipped.add(err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Enforces the retention setting now and then every RUN_EVERY.
+pub fn spawn(store: Arc<dyn EventStore>, settings: Arc<Mutex<UserSettings>>) {
+    std::thread::spawn(move || loop {
+        let raw_days = settings.lock().unwrap().retention.raw_days;
+        if raw_days > 0 {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis();
+            match store.expire(now - raw_days as u128 * DAY) {
+                Ok(0) => {}
+                Ok(folded) => log::info!("downsampled {folded} rocks older than {raw_days} days"),
+                Err(err) => log::error!("failed to downsample: {err}"),
+            }
+        }
+
+        std::thread::sleep(RUN_EVERY);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::rocks::RocksStore;
+    use crate::store::tests::{code, event, press, Scratch};
+    use crate::MINUTE;
+
+    // an hour on 2023-11-14
+    const AT: u128 = 1_700_002_800_000;
+
+    fn total(store: &dyn EventStore) -> u64 {
+        store.counts(0, HOUR).unwrap().iter().map(|count| count.count).sum()
+    }
+
+    #[test]
+    fn downsampled_hours_keep_their_counts() {
+        let scratch = Scratch::new("downsample");
+        let store = RocksStore::open(&scratch.path("coca-rocks.db")).unwrap();
+        store.append(&[
+            press(AT + 1, "game", gilrs::Button::South),
+            press(AT + 2, "game", gilrs::Button::South),
+            event(AT + 3, "game", gilrs::EventType::ButtonReleased(gilrs::Button::South, code())),
+            event(AT + 4, "game", gilrs::EventType::Connected),
+            press(AT + HOUR, "game", gilrs::Button::North),
+        ]).unwrap();
+        assert_eq!(total(&store), 5);
+
+        // only whole hours go, so the last press stays raw
+        assert_eq!(store.expire(AT + HOUR + 5).unwrap(), 4);
+        let mut raw = 0;
+        store.scan(0, u128::MAX, &mut |_| {
+            raw += 1;
+            true
+        }).unwrap();
+        assert_eq!(raw, 1);
+
+        let summary = store.summary("game", 0).unwrap();
+        assert_eq!(summary.presses[&gilrs::Button::South], 2);
+        assert_eq!(summary.presses[&gilrs::Button::North], 1);
+        assert_eq!(total(&store), 5);
+
+        // counted again from the raw rocks and the summaries, not just the raw rocks
+        store.rebuild().unwrap();
+        assert_eq!(total(&store), 5);
+    }
+
+    #[test]
+    fn downsampled_hours_move_with_their_app() {
+        let scratch = Scratch::new("downsampled relabel");
+        let store = RocksStore::open(&scratch.path("coca-rocks.db")).unwrap();
+        store.append(&[
+            press(AT + 1, "game - 60 FPS", gilrs::Button::South),
+            press(AT + 5 * MINUTE, "game - 60 FPS", gilrs::Button::South),
+            event(AT + 6 * MINUTE, "game - 60 FPS", gilrs::EventType::Connected),
+            press(AT + 7 * MINUTE, "other", gilrs::Button::North),
+        ]).unwrap();
+        store.expire(AT + HOUR).unwrap();
+
+        let moved = store.relabel_downsampled(&mut |app| (app == "game - 60 FPS").then(|| "game".to_string())).unwrap();
+        assert_eq!(moved, 3);
+
+        let summary = store.summary("game", 0).unwrap();
+        assert_eq!(summary.presses[&gilrs::Button::South], 2);
+        assert!(store.summary("game - 60 FPS", 0).unwrap().presses.is_empty());
+
+        // every minute went with it, not just the hour
+        for width in [MINUTE, HOUR, DAY] {
+            let mut apps = HashMap::<String, u64>::new();
+            for count in store.counts(0, width).unwrap() {
+                *apps.entry(count.app).or_default() += count.count;
+            }
+            assert_eq!(apps.get("game - 60 FPS").copied().unwrap_or(0), 0);
+            assert_eq!(apps["game"], 3);
+            assert_eq!(apps["other"], 1);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use rocksdb::{ColumnFamilyDescriptor, MergeOperands, Options, WriteBatch, DB};
+
+use crate::store::{Skipped, BATCH_SIZE};
+use crate::{integrity, retention, Rock, DAY, HOUR, MINUTE};
+
+// counters for every (bucket, kind, app, pad), so the graphs dont have to read every rock
+// key: [bucket start (big endian u64 ms), kind, app id, pad id]
+// value: count as a little endian i64, only ever merged into (-1 when a rock is deleted)
+#[derive(Clone, Copy, PartialEq)]
+pub enum Granularity {
+    Minute,
+    Hour,
+    Day,
+}
+
+pub const GRANULARITIES: [Granularity; 3] = [Granularity::Minute, Granularity::Hour, Granularity::Day];
+
+// set once the rollups have been built from the raw events
+pub const BUILT_KEY: &[u8] = b"rollups";
+
+impl Granularity {
+    pub fn cf(&self) -> &'static str {
+        match self {
+            Granularity::Minute => "rollup_minute",
+            Granularity::Hour => "rollup_hour",
+            Granularity::Day => "rollup_day",
+        }
+    }
+
+    pub fn width(&self) -> u128 {
+        match self {
+            Granularity::Minute => MINUTE,
+            Granularity::Hour => HOUR,
+            Granularity::Day => DAY,
+        }
+    }
+
+    pub fn bucket(&self, at: u128) -> u64 {
+        (at - at % self.width()) as u64
+    }
+
+    /// The coarsest rollup no wider than `width`.
+    pub fn for_width(width: u128) -> Granularity {
+        *GRANULARITIES.iter().rev().find(|g| g.width() <= width).unwrap_or(&Granularity::Minute)
+    }
+}
+
+// gilrs doesnt give the variants a number, and these end up on disk so they cant move
+pub fn event_kind(event: &gilrs::EventType) -> u8 {
+    match event {
+        gilrs::EventType::ButtonPressed(..) => 0,
+        gilrs::EventType::ButtonRepeated(..) => 1,
+        gilrs::EventType::ButtonReleased(..) => 2,
+        gilrs::EventType::ButtonChanged(..) => 3,
+        gilrs::EventType::AxisChanged(..) => 4,
+        gilrs::EventType::Connected => 5,
+        gilrs::EventType::Disconnected => 6,
+        gilrs::EventType::Dropped => 7,
+    }
+}
+
+fn add(_key: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    let mut count = existing.map(decode_count).unwrap_or(0);
+    for op in operands {
+        count += decode_count(op);
+    }
+
+    Some(count.to_le_bytes().to_vec())
+}
+
+fn decode_count(value: &[u8]) -> i64 {
+    value.try_into().map(i64::from_le_bytes).unwrap_or(0)
+}
+
+pub fn descriptors() -> Vec<ColumnFamilyDescriptor> {
+    GRANULARITIES.iter().map(|g| {
+        let mut opts = Options::default();
+        opts.set_merge_operator_associative("add", add);
+        ColumnFamilyDescriptor::new(g.cf(), opts)
+    }).collect()
+}
+
+const KEY_LEN: usize = 8 + 1 + 4 + 4;
+
+fn encode(bucket: u64, kind: u8, app: u32, pad: u32) -> [u8; KEY_LEN] {
+    let mut key = [0u8; KEY_LEN];
+    key[..8].copy_from_slice(&bucket.to_be_bytes());
+    key[8] = kind;
+    key[9..13].copy_from_slice(&app.to_be_bytes());
+    key[13..].copy_from_slice(&pad.to_be_bytes());
+    key
+}
+
+pub struct Count {
+    pub bucket: u64,
+    pub kind: u8,
+    pub app: u32,
+    pub pad: u32,
+    pub count: i64,
+}
+
+fn decode(key: &[u8], value: &[u8]) -> Option<Count> {
+    if key.len() != KEY_LEN {
+        return None;
+    }
+
+    Some(Count {
+        bucket: u64::from_be_bytes(key[..8].try_into().unwrap()),
+        kind: key[8],
+        app: u32::from_be_bytes(key[9..13].try_into().unwrap()),
+        pad: u32::from_be_bytes(key[13..].try_into().unwrap()),
+        count: decode_count(value),
+    })
+}
+
+/// Adds the rock to every rollup, in the same batch as the rock so they cant drift.
+pub fn record(db: &DB, batch: &mut WriteBatch, rock: &Rock) {
+    count(db, batch, rock, 1);
+}
+
+/// Takes the rock back out of every rollup, for when the rock itself is deleted.
+pub fn remove(db: &DB, batch: &mut WriteBatch, rock: &Rock) {
+    count(db, batch, rock, -1);
+}
+
+fn count(db: &DB, batch: &mut WriteBatch, rock: &Rock, n: i64) {
+    add_to(db, batch, rock.at, event_kind(&rock.event), rock.app, rock.pad, n);
+}
+
+fn add_to(db: &DB, batch: &mut WriteBatch, at: u128, kind: u8, app: u32, pad: u32, n: i64) {
+    for g in GRANULARITIES {
+        let cf = db.cf_handle(g.cf()).unwrap();
+        batch.merge_cf(cf, encode(g.bucket(at), kind, app, pad), n.to_le_bytes());
+    }
+}
+
+/// Moves one downsampled hour of `app` and `pad` over to `to`, in every rollup.
+/// The minutes are moved one by one, the rollups still have them where the raw events were.
+pub fn move_hour(db: &DB, batch: &mut WriteBatch, at: u64, app: u32, pad: u32, to: u32) -> Result<(), String> {
+    let minutes = db.cf_handle(Granularity::Minute.cf()).ok_or("missing rollup column family")?;
+    let mut moving = |cf, bucket, kind, n: i64| {
+        batch.merge_cf(cf, encode(bucket, kind, app, pad), (-n).to_le_bytes());
+        batch.merge_cf(cf, encode(bucket, kind, to, pad), n.to_le_bytes());
+    };
+
+    for (kind, n) in hour(db, at, app, pad)? {
+        // the day has the other hours in it too, so only this hours share moves
+        for g in [Granularity::Hour, Granularity::Day] {
+            moving(db.cf_handle(g.cf()).unwrap(), g.bucket(at as u128), kind, n as i64);
+        }
+
+        for minute in 0..(HOUR / MINUTE) as u64 {
+            let bucket = at + minute * MINUTE as u64;
+            if let Some(value) = db.get_cf(minutes, encode(bucket, kind, app, pad)).map_err(|err| err.to_string())? {
+                moving(minutes, bucket, kind, decode_count(&value));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// the hourly counts for one app and pad by kind
+fn hour(db: &DB, hour: u64, app: u32, pad: u32) -> Result<HashMap<u8, u64>, String> {
+    let cf = db.cf_handle(Granularity::Hour.cf()).ok_or("missing rollup column family")?;
+
+    let mut kinds = HashMap::new();
+    for kind in 0..=event_kind(&gilrs::EventType::Dropped) {
+        if let Some(value) = db.get_cf(cf, encode(hour, kind, app, pad)).map_err(|err| err.to_string())? {
+            let count = decode_count(&value);
+            if count > 0 {
+                kinds.insert(kind, count as u64);
+            }
+        }
+    }
+
+    Ok(kinds)
+}
+
+/// Every count from `start` on, newest first.
+/// The bucket holding `start` is counted whole, so this can be off by up to one bucket.
+pub fn since(db: &DB, g: Granularity, start: u128) -> Result<Vec<Count>, String> {
+    let cf = db.cf_handle(g.cf()).ok_or("missing rollup column family")?;
+    let first = g.bucket(start);
+
+    let mut counts = Vec::new();
+    for row in db.iterator_cf(cf, rocksdb::IteratorMode::End) {
+        let (key, value) = row.map_err(|err| err.to_string())?;
+        let Some(count) = decode(&key, &value) else {
+            log::warn!("skipping bad rollup key in {}", g.cf());
+            continue;
+        };
+
+        if count.bucket < first {
+            break; // reversed like the raw events
+        }
+
+        // whatever was deleted or relabeled leaves its keys behind at 0, they arent anything
+        if count.count > 0 {
+            counts.push(count);
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Throws the rollups away and counts every raw event and downsampled hour again.
+/// A downsampled hour only knows its hour, so its events all land in the first minute.
+pub fn rebuild(db: &DB) -> Result<usize, String> {
+    for g in GRANULARITIES {
+        let cf = db.cf_handle(g.cf()).ok_or("missing rollup column family")?;
+        db.delete_range_cf(cf, [0u8; 8].as_slice(), [0xffu8; 9].as_slice()).map_err(|err| err.to_string())?;
+    }
+
+    let mut counted = 0;
+    let mut skipped = Skipped::new("rollup rebuild");
+    let mut batch = WriteBatch::default();
+    for row in db.iterator(rocksdb::IteratorMode::Start) {
+        let (key, value) = row.map_err(|err| err.to_string())?;
+        let rock = match integrity::check(&key, &value) {
+            Ok(rock) => rock,
+            Err(bad) => {
+                skipped.add(bad);
+                continue;
+            }
+        };
+
+        record(db, &mut batch, &rock);
+        counted += 1;
+
+        if batch.len() >= BATCH_SIZE {
+            db.write(std::mem::take(&mut batch)).map_err(|err| err.to_string())?;
+        }
+    }
+
+    retention::each(db, &mut |hour, summary| {
+        for (kind, n) in summary.kinds {
+            add_to(db, &mut batch, hour.at as u128, kind, hour.app, hour.pad, n as i64);
+            counted += n as usize;
+        }
+
+        if batch.len() >= BATCH_SIZE {
+            db.write(std::mem::take(&mut batch)).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    })?;
+    db.write(batch).map_err(|err| err.to_string())?;
+
+    let meta = db.cf_handle(crate::migrate::META_CF).ok_or("missing meta column family")?;
+    db.put_cf(meta, BUILT_KEY, [1]).map_err(|err| err.to_string())?;
+
+    Ok(counted)
+}
+
+/// Builds the rollups the first time a db without them is opened.
+pub fn ensure(db: &DB) -> Result<(), String> {
+    let meta = db.cf_handle(crate::migrate::META_CF).ok_or("missing meta column family")?;
+    if db.get_cf(meta, BUILT_KEY).map_err(|err| err.to_string())?.is_some() {
+        return Ok(());
+    }
+
+    let counted = rebuild(db)?;
+    log::info!("built rollups from {counted} rocks");
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use super::{Event, EventStore};
+use crate::controllers::Controller;
+use crate::identity::{Identity, Known};
+
+#[derive(Default)]
+struct Inner {
+    events: BTreeMap<(u128, u64), Event>, // (at, seq) so two events in the same ms both stay
+    next: u64,
+    known: HashMap<String, Known>,
+    controllers: BTreeMap<String, Controller>,
+}
+
+/// Keeps everything in memory and forgets it on quit.
+/// Good for tests and for trying coca without touching the disk.
+#[derive(Default)]
+pub struct MemoryStore {
+    inner: Mutex<Inner>,
+}
+
+impl EventStore for MemoryStore {
+    fn append(&self, events: &[Event]) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        for event in events {
+            let seq = inner.next;
+            inner.next += 1;
+            inner.events.insert((event.at, seq), event.clone());
+        }
+
+        Ok(())
+    }
+
+    fn scan(&self, start: u128, end: u128, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String> {
+        // range panics on a backwards range
+        if start >= end {
+            return Ok(());
+        }
+
+        let inner = self.inner.lock().unwrap();
+        for event in inner.events.range((start, 0)..(end, 0)).rev().map(|(_, event)| event) {
+            if !f(event.clone()) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scan_app(&self, app: &str, start: u128, end: u128, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String> {
+        self.scan(start, end, &mut |event| event.app != app || f(event))
+    }
+
+    fn delete(&self, start: u128, end: u128) -> Result<usize, String> {
+        if start >= end {
+            return Ok(0);
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let keys: Vec<(u128, u64)> = inner.events.range((start, 0)..(end, 0)).map(|(key, _)| *key).collect();
+        for key in &keys {
+            inner.events.remove(key);
+        }
+
+        Ok(keys.len())
+    }
+
+    fn relabel(&self, start: u128, end: u128, f: &mut dyn FnMut(&Event) -> Option<String>) -> Result<usize, String> {
+        if start >= end {
+            return Ok(0);
+        }
+
+        let mut moved = 0;
+        let mut inner = self.inner.lock().unwrap();
+        for event in inner.events.range_mut((start, 0)..(end, 0)).map(|(_, event)| event) {
+            if let Some(app) = f(event) {
+                event.app = app;
+                moved += 1;
+            }
+        }
+
+        Ok(moved)
+    }
+
+    fn identify(&self, app: &str, identity: &Identity) -> Result<(), String> {
+        self.inner.lock().unwrap().known.entry(app.to_string()).or_default().learn(identity);
+        Ok(())
+    }
+
+    fn known(&self, app: &str) -> Result<Option<Known>, String> {
+        Ok(self.inner.lock().unwrap().known.get(app).cloned())
+    }
+
+    fn save_controller(&self, controller: &Controller) -> Result<(), String> {
+        self.inner.lock().unwrap().controllers.insert(controller.id.clone(), controller.clone());
+        Ok(())
+    }
+
+    fn controllers(&self) -> Result<Vec<Controller>, String> {
+        Ok(self.inner.lock().unwrap().controllers.values().cloned().collect())
+    }
+}
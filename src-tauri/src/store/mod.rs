@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backup::Backup;
+use crate::controllers::Controller;
+use crate::identity::{Identity, Known};
+use crate::integrity::Report;
+use crate::AXIS_H;
+
+pub mod memory;
+pub mod rocks;
+pub mod sqlite;
+
+// rows per WriteBatch when rocks rewrites a lot at once, so a big db doesnt sit in memory
+pub const BATCH_SIZE: usize = 10_000;
+
+/// A rock with its names filled in, what every store takes and hands back.
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub at: u128,
+    pub pad: String,
+    pub app: String,
+    pub event: gilrs::EventType,
+}
+
+pub struct Count {
+    pub bucket: u128,
+    pub app: String,
+    pub pad: String,
+    pub count: u64,
+}
+
+/// Everything app_stats needs, without the events.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Summary {
+    pub presses: HashMap<gilrs::Button, u64>,
+    // same buckets as Axis.pos_buckets, with AXIS_H
+    pub axes: HashMap<gilrs::Axis, HashMap<i32, u64>>,
+    // every event by rollup::event_kind, so the rollups can be counted again from a downsampled hour
+    pub kinds: HashMap<u8, u64>,
+}
+
+impl Summary {
+    pub fn add(&mut self, event: &gilrs::EventType) {
+        *self.kinds.entry(crate::rollup::event_kind(event)).or_default() += 1;
+        match event {
+            gilrs::EventType::ButtonPressed(button, _code) => {
+                *self.presses.entry(*button).or_default() += 1;
+            }
+            gilrs::EventType::AxisChanged(axis, pos, _code) => {
+                let bucket = (pos / AXIS_H).floor() as i32;
+                *self.axes.entry(*axis).or_default().entry(bucket).or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn merge(&mut self, other: Summary) {
+        for (button, presses) in other.presses {
+            *self.presses.entry(button).or_default() += presses;
+        }
+
+        for (axis, buckets) in other.axes {
+            let axis = self.axes.entry(axis).or_default();
+            for (bucket, n) in buckets {
+                *axis.entry(bucket).or_default() += n;
+            }
+        }
+
+        for (kind, n) in other.kinds {
+            *self.kinds.entry(kind).or_default() += n;
+        }
+    }
+}
+
+/// Bad rows readers stepped over since startup, for the integrity report.
+pub static SKIPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts the bad rows one read steps over and warns once when the read is done, rather than per row.
+pub struct Skipped {
+    what: &'static str,
+    n: usize,
+    first: Option<String>,
+}
+
+impl Skipped {
+    pub fn new(what: &'static str) -> Skipped {
+        Skipped { what, n: 0, first: None }
+    }
+
+    pub fn add(&mut self, err: impl std::fmt::Display) {
+        if self.first.is_none() {
+            self.first = Some(err.to_string());
+        }
+        self.n += 1;
+    }
+}
+
+impl Drop for Skipped {
+    fn drop(&mut self) {
+        if self.n == 0 {
+            return;
+        }
+
+        SKIPPED.fetch_add(self.n, Ordering::Relaxed);
+        log::warn!("{} skipped {} bad rows, the first was {}", self.what, self.n, self.first.as_deref().unwrap_or("?"));
+    }
+}
+
+/// Every kind_name, in EventType order.
+pub const KINDS: &[&str] = &[
+    "ButtonPressed",
+    "ButtonRepeated",
+    "ButtonReleased",
+    "ButtonChanged",
+    "AxisChanged",
+    "Connected",
+    "Disconnected",
+    "Dropped",
+];
+
+// the variant name, how it reads in rocks.jsonl
+pub fn kind_name(event: &gilrs::EventType) -> &'static str {
+    match event {
+        gilrs::EventType::ButtonPressed(..) => "ButtonPressed",
+        gilrs::EventType::ButtonRepeated(..) => "ButtonRepeated",
+        gilrs::EventType::ButtonReleased(..) => "ButtonReleased",
+        gilrs::EventType::ButtonChanged(..) => "ButtonChanged",
+        gilrs::EventType::AxisChanged(..) => "AxisChanged",
+        gilrs::EventType::Connected => "Connected",
+        gilrs::EventType::Disconnected => "Disconnected",
+        gilrs::EventType::Dropped => "Dropped",
+    }
+}
+
+/// Where the events live.
+/// Only append, scan, scan_app, delete and relabel are required, the rest have slow but correct defaults
+/// that a store can replace with whatever it keeps on the side.
+pub trait EventStore: Send + Sync {
+    /// Adds the events, all or nothing.
+    fn append(&self, events: &[Event]) -> Result<(), String>;
+
+    /// Calls `f` on every event with `start <= at < end`, newest first, until it returns false.
+    fn scan(&self, start: u128, end: u128, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String>;
+
+    /// Same as scan, but only the events for `app`.
+    fn scan_app(&self, app: &str, start: u128, end: u128, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String>;
+
+    /// Removes every event with `start <= at < end`, returns how many went.
+    fn delete(&self, start: u128, end: u128) -> Result<usize, String>;
+
+    /// Calls `f` on every event with `start <= at < end` and moves it to whatever app `f` returns.
+    /// Returns how many moved.
+    fn relabel(&self, start: u128, end: u128, f: &mut dyn FnMut(&Event) -> Option<String>) -> Result<usize, String>;
+
+    /// Events per app and pad from `start` on, in buckets no wider than `width`, newest first.
+    /// The bucket holding `start` may be counted whole.
+    fn counts(&self, start: u128, width: u128) -> Result<Vec<Count>, String> {
+        let width = width.max(1);
+        let mut counts = HashMap::<(u128, String, String), u64>::new();
+        self.scan(start, u128::MAX, &mut |event| {
+            *counts.entry((event.at - event.at % width, event.app, event.pad)).or_default() += 1;
+            true
+        })?;
+
+        let mut counts: Vec<Count> = counts.into_iter()
+            .map(|((bucket, app, pad), count)| Count { bucket, app, pad, count })
+            .collect();
+        counts.sort_by(|a, b| b.bucket.cmp(&a.bucket));
+        Ok(counts)
+    }
+
+    /// Presses and axis positions for `app` from `start` on.
+    fn summary(&self, app: &str, start: u128) -> Result<Summary, String> {
+        let mut summary = Summary::default();
+        self.scan_app(app, start, u128::MAX, &mut |event| {
+            summary.add(&event.event);
+            true
+        })?;
+
+        Ok(summary)
+    }
+
+    /// Same as summary, split by the pad the events came from.
+    fn summary_by_pad(&self, app: &str, start: u128) -> Result<HashMap<String, Summary>, String> {
+        let mut summaries = HashMap::<String, Summary>::new();
+        self.scan_app(app, start, u128::MAX, &mut |event| {
+            summaries.entry(event.pad).or_default().add(&event.event);
+            true
+        })?;
+
+        Ok(summaries)
+    }
+
+    /// Same as relabel for what expire folded the raw events into, by app since thats all that is left of them.
+    /// Returns how many events moved. Stores that keep everything raw have nothing to move.
+    fn relabel_downsampled(&self, _f: &mut dyn FnMut(&str) -> Option<String>) -> Result<usize, String> {
+        Ok(0)
+    }
+
+    /// Lets go of the raw events before `before`, keeping enough that summary still works.
+    /// Stores that cant do that keep everything.
+    fn expire(&self, _before: u128) -> Result<usize, String> {
+        Ok(0)
+    }
+
+    /// Throws away anything derived from the raw events and works it out again.
+    fn rebuild(&self) -> Result<usize, String> {
+        Ok(0)
+    }
+
+    /// Backs everything up into `dir`, keeping the newest `keep` backups (0 keeps all).
+    fn backup(&self, _dir: &str, _keep: u32) -> Result<Backup, String> {
+        Err("only the rocksdb store can be backed up".to_string())
+    }
+
+    /// Looks for rows that wont read back, and with `quarantine` moves them somewhere they cant hurt.
+    fn check(&self, _quarantine: bool) -> Result<Report, String> {
+        Err("only the rocksdb store can be checked".to_string())
+    }
+
+    /// Remembers that `app` was `identity` (its exe, pid and raw title) the last time it had focus.
+    /// Stores without anywhere to put it forget.
+    fn identify(&self, _app: &str, _identity: &Identity) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Everything `identify` was told about `app`.
+    fn known(&self, _app: &str) -> Result<Option<Known>, String> {
+        Ok(None)
+    }
+
+    /// Adds a controller, or replaces the one with the same id.
+    fn save_controller(&self, _controller: &Controller) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Every controller save_controller was given.
+    fn controllers(&self) -> Result<Vec<Controller>, String> {
+        Ok(Vec::new())
+    }
+}
+
+/// The store everything else holds, so it can be swapped out while the app runs (restoring a backup).
+/// Nothing outside hands out the inner Arc, so a swap is the only thing holding it.
+pub struct Swap {
+    inner: RwLock<Arc<dyn EventStore>>,
+}
+
+impl Swap {
+    pub fn new(store: Arc<dyn EventStore>) -> Swap {
+        Swap { inner: RwLock::new(store) }
+    }
+
+    /// Waits for every call in flight, then hands the old store to `f` and uses whatever it gives back.
+    /// Calls made meanwhile wait for it.
+    pub fn replace(&self, f: impl FnOnce(Arc<dyn EventStore>) -> Arc<dyn EventStore>) {
+        let mut inner = self.inner.write().unwrap();
+        // something has to sit there while f runs, nobody can see it though
+        let old = std::mem::replace(&mut *inner, Arc::new(memory::MemoryStore::default()));
+        *inner = f(old);
+    }
+}
+
+impl EventStore for Swap {
+    fn append(&self, events: &[Event]) -> Result<(), String> {
+        self.inner.read().unwrap().append(events)
+    }
+
+    fn scan(&self, start: u128, end: u128, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String> {
+        self.inner.read().unwrap().scan(start, end, f)
+    }
+
+    fn scan_app(&self, app: &str, start: u128, end: u128, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String> {
+        self.inner.read().unwrap().scan_app(app, start, end, f)
+    }
+
+    fn delete(&self, start: u128, end: u128) -> Result<usize, String> {
+        self.inner.read().unwrap().delete(start, end)
+    }
+
+    fn relabel(&self, start: u128, end: u128, f: &mut dyn FnMut(&Event) -> Option<String>) -> Result<usize, String> {
+        self.inner.read().unwrap().relabel(start, end, f)
+    }
+
+    fn relabel_downsampled(&self, f: &mut dyn FnMut(&str) -> Option<String>) -> Result<usize, String> {
+        self.inner.read().unwrap().relabel_downsampled(f)
+    }
+
+    fn counts(&self, start: u128, width: u128) -> Result<Vec<Count>, String> {
+        self.inner.read().unwrap().counts(start, width)
+    }
+
+    fn summary(&self, app: &str, start: u128) -> Result<Summary, String> {
+        self.inner.read().unwrap().summary(app, start)
+    }
+
+    fn expire(&self, before: u128) -> Result<usize, String> {
+        self.inner.read().unwrap().expire(before)
+    }
+
+    fn rebuild(&self) -> Result<usize, String> {
+        self.inner.read().unwrap().rebuild()
+    }
+
+    fn backup(&self, dir: &str, keep: u32) -> Result<Backup, String> {
+        self.inner.read().unwrap().backup(dir, keep)
+    }
+
+    fn check(&self, quarantine: bool) -> Result<Report, String> {
+        self.inner.read().unwrap().check(quarantine)
+    }
+
+    fn identify(&self, app: &str, identity: &Identity) -> Result<(), String> {
+        self.inner.read().unwrap().identify(app, identity)
+    }
+
+    fn known(&self, app: &str) -> Result<Option<Known>, String> {
+        self.inner.read().unwrap().known(app)
+    }
+
+    fn summary_by_pad(&self, app: &str, start: u128) -> Result<HashMap<String, Summary>, String> {
+        self.inner.read().unwrap().summary_by_pad(app, start)
+    }
+
+    fn save_controller(&self, controller: &Controller) -> Result<(), String> {
+        self.inner.read().unwrap().save_controller(controller)
+    }
+
+    fn controllers(&self) -> Result<Vec<Controller>, String> {
+        self.inner.read().unwrap().controllers()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::Event;
+
+    /// A dir under the temp dir for one test, gone once dropped.
+    pub struct Scratch(PathBuf);
+
+    impl Scratch {
+        pub fn new(name: &str) -> Scratch {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+            let dir = std::env::temp_dir().join(format!("coca-test-{name}-{}-{nanos}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Scratch(dir)
+        }
+
+        pub fn path(&self, name: &str) -> String {
+            self.0.join(name).to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // codes cant be made, only deserialized the way the platform writes them, linux first then windows and mac
+    pub fn code() -> gilrs::ev::Code {
+        serde_json::from_str(r#"{"kind":1,"code":304}"#)
+            .or_else(|_| serde_json::from_str(r#"{"page":9,"usage":1}"#))
+            .expect("no way to make an event code on this platform")
+    }
+
+    pub fn event(at: u128, app: &str, event: gilrs::EventType) -> Event {
+        Event { at, pad: "pad".to_string(), app: app.to_string(), event }
+    }
+
+    pub fn press(at: u128, app: &str, button: gilrs::Button) -> Event {
+        event(at, app, gilrs::EventType::ButtonPressed(button, code()))
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use rocksdb::{ColumnFamilyDescriptor, Options, WriteBatch, DB};
+
+use super::{Count, Event, EventStore, Skipped, Summary, BATCH_SIZE};
+use crate::backup::{self, Backup};
+use crate::controllers::{self, Controller};
+use crate::identity::{Identity, Known};
+use crate::integrity::{self, Report};
+use crate::{decode_key, dict, encode_key, index, migrate, retention, rollup, write_rock, Rock};
+
+/// Opens the db with every column family coca keeps, making any that are missing.
+pub fn open_db(path: &str) -> Result<DB, String> {
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    opts.create_missing_column_families(true);
+
+    let mut cfs = vec![ColumnFamilyDescriptor::new(migrate::META_CF, Options::default())];
+    cfs.extend(rollup::descriptors());
+    cfs.push(ColumnFamilyDescriptor::new(index::APP_INDEX_CF, Options::default()));
+    cfs.push(ColumnFamilyDescriptor::new(dict::DICT_CF, Options::default()));
+    cfs.push(ColumnFamilyDescriptor::new(retention::DOWNSAMPLED_CF, Options::default()));
+    cfs.push(ColumnFamilyDescriptor::new(integrity::QUARANTINE_CF, Options::default()));
+    cfs.push(ColumnFamilyDescriptor::new(controllers::CONTROLLERS_CF, Options::default()));
+
+    // open default: 15.5MiB (111k)
+    DB::open_cf_descriptors(&opts, path, cfs).map_err(|err| err.to_string())
+}
+
+/// The original store, rocks keyed by time with rollups, an app index and a dictionary on the side.
+pub struct RocksStore {
+    db: DB,
+    dict: dict::Dict,
+    nonce: Mutex<u16>, // i think this is the right thing, rather than salt/pepper
+    // held by everything that reads rocks or summaries and writes back what it worked out from them,
+    // the retention thread and the commands can otherwise undo each other
+    rewriting: Mutex<()>,
+}
+
+impl RocksStore {
+    /// Opens the db, brings it up to DB_VERSION and builds whatever it is missing.
+    pub fn open(path: &str) -> Result<RocksStore, String> {
+        let db = open_db(path)?;
+
+        // check if the db is the proper version
+        let on_progress = |p: &migrate::Progress| log::info!("{} (v{} -> v{}): {}/~{} rows", p.name, p.from, p.to, p.done, p.total);
+        match migrate::run(&db, false, &on_progress) {
+            Ok(report) if report.from != report.to => log::info!("migrated {} rows from db version {} to {}", report.rewritten, report.from, report.to),
+            Ok(_) => log::debug!("db is at version {}", crate::DB_VERSION),
+            Err(err) => log::error!("failed to migrate db, it will not be readable until this is fixed: {err}"),
+        }
+
+        if let Err(err) = rollup::ensure(&db) {
+            log::error!("failed to build rollups: {err}");
+        }
+
+        if let Err(err) = index::ensure(&db) {
+            log::error!("failed to build the app index: {err}");
+        }
+
+        Ok(RocksStore { db, dict: dict::Dict::default(), nonce: Mutex::new(0), rewriting: Mutex::new(()) })
+    }
+
+    /// How many hours only exist downsampled.
+    pub fn downsampled(&self) -> Result<usize, String> {
+        let mut hours = 0;
+        retention::each(&self.db, &mut |_, _| {
+            hours += 1;
+            Ok(())
+        })?;
+        Ok(hours)
+    }
+
+    fn event(&self, rock: Rock) -> Result<Event, String> {
+        Ok(Event {
+            at: rock.at,
+            pad: self.dict.name(&self.db, dict::Kind::Pad, rock.pad)?,
+            app: self.dict.name(&self.db, dict::Kind::App, rock.app)?,
+            event: rock.event,
+        })
+    }
+
+    // rebuild, for callers already holding the rewriting lock
+    fn rederive(&self) -> Result<usize, String> {
+        index::rebuild(&self.db)?;
+        rollup::rebuild(&self.db)
+    }
+
+    // a bad row is counted in `skipped` and comes back as None, only a db error is an error
+    fn get(&self, pk: &[u8], skipped: &mut Skipped) -> Result<Option<Rock>, String> {
+        let Some(value) = self.db.get(pk).map_err(|err| err.to_string())? else {
+            log::warn!("app index points at a missing rock");
+            return Ok(None);
+        };
+
+        match integrity::check(pk, &value) {
+            Ok(rock) => Ok(Some(rock)),
+            Err(bad) => {
+                skipped.add(bad);
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl EventStore for RocksStore {
+    fn append(&self, events: &[Event]) -> Result<(), String> {
+        let mut batch = WriteBatch::default();
+        let mut nonce = self.nonce.lock().unwrap();
+
+        for event in events {
+            let rock = Rock {
+                at: event.at,
+                pad: self.dict.intern(&self.db, dict::Kind::Pad, &event.pad)?,
+                app: self.dict.intern(&self.db, dict::Kind::App, &event.app)?,
+                event: event.event,
+            };
+
+            // there can be multiple with the same nonce, as long as they arent at the same time
+            // the nonce starts over every run though, and imports and merges write into the past where a key can be taken
+            let mut pk = encode_key(rock.at, *nonce);
+            let mut tries = 0;
+            while self.db.get_pinned(pk).map_err(|err| err.to_string())?.is_some() {
+                tries += 1;
+                if tries == u16::MAX {
+                    return Err(format!("every key at {} is taken", rock.at));
+                }
+                *nonce = nonce.wrapping_add(1);
+                pk = encode_key(rock.at, *nonce);
+            }
+            *nonce = nonce.wrapping_add(1);
+
+            write_rock(&self.db, &mut batch, &pk, bincode::serialize(&rock).unwrap(), &rock);
+        }
+
+        self.db.write(batch).map_err(|err| err.to_string())
+    }
+
+    fn scan(&self, start: u128, end: u128, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String> {
+        let mut skipped = Skipped::new("scan");
+        let last = encode_key(end, 0);
+        for row in self.db.iterator(rocksdb::IteratorMode::From(&last, rocksdb::Direction::Reverse)) {
+            let (key, value) = row.map_err(|err| err.to_string())?;
+
+            let rock = match integrity::check(&key, &value) {
+                Ok(rock) => rock,
+                Err(bad) => {
+                    skipped.add(bad);
+                    continue;
+                }
+            };
+
+            if rock.at >= end {
+                continue;
+            }
+
+            // we are reversed, so we can break
+            if rock.at < start {
+                break;
+            }
+
+            if !f(self.event(rock)?) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scan_app(&self, app: &str, start: u128, end: u128, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String> {
+        // never seen, so there is nothing to find
+        let Some(id) = self.dict.lookup(&self.db, dict::Kind::App, app)? else {
+            return Ok(());
+        };
+
+        let mut failed = None;
+        let mut skipped = Skipped::new("scan_app");
+        index::scan(&self.db, id, start, end, &mut |pk| {
+            match self.get(pk, &mut skipped).and_then(|rock| rock.map(|rock| self.event(rock)).transpose()) {
+                Ok(Some(event)) => f(event),
+                Ok(None) => true,
+                Err(err) => {
+                    failed = Some(err);
+                    false
+                }
+            }
+        })?;
+
+        failed.map_or(Ok(()), Err)
+    }
+
+    fn delete(&self, start: u128, end: u128) -> Result<usize, String> {
+        let _rewriting = self.rewriting.lock().unwrap();
+        let mut deleted = 0;
+        let mut batch = WriteBatch::default();
+
+        let mut skipped = Skipped::new("delete");
+        let first = encode_key(start, 0);
+        for row in self.db.iterator(rocksdb::IteratorMode::From(&first, rocksdb::Direction::Forward)) {
+            let (pk, value) = row.map_err(|err| err.to_string())?;
+            let Ok(at) = decode_key(&pk) else {
+                skipped.add("key wont decode");
+                continue;
+            };
+
+            if at >= end {
+                break;
+            }
+
+            // a bad rock still goes, there is just nothing derived from it we can find to take out
+            batch.delete(&pk);
+            match integrity::check(&pk, &value) {
+                Ok(rock) => {
+                    rollup::remove(&self.db, &mut batch, &rock);
+                    index::remove(&self.db, &mut batch, &pk, &rock);
+                }
+                Err(bad) => skipped.add(bad),
+            }
+            deleted += 1;
+
+            if deleted % BATCH_SIZE == 0 {
+                self.db.write(std::mem::take(&mut batch)).map_err(|err| err.to_string())?;
+            }
+        }
+
+        self.db.write(batch).map_err(|err| err.to_string())?;
+        Ok(deleted)
+    }
+
+    fn relabel(&self, start: u128, end: u128, f: &mut dyn FnMut(&Event) -> Option<String>) -> Result<usize, String> {
+        let _rewriting = self.rewriting.lock().unwrap();
+        let mut moved = 0;
+        let mut batch = WriteBatch::default();
+        let mut skipped = Skipped::new("relabel");
+
+        let first = encode_key(start, 0);
+        for row in self.db.iterator(rocksdb::IteratorMode::From(&first, rocksdb::Direction::Forward)) {
+            let (pk, value) = row.map_err(|err| err.to_string())?;
+            let rock = match integrity::check(&pk, &value) {
+                Ok(rock) => rock,
+                Err(bad) => {
+                    skipped.add(bad);
+                    continue;
+                }
+            };
+
+            if rock.at >= end {
+                break;
+            }
+
+            let Some(app) = f(&self.event(rock)?) else {
+                continue;
+            };
+
+            let moved_rock = Rock { app: self.dict.intern(&self.db, dict::Kind::App, &app)?, ..rock };
+            if moved_rock.app == rock.app {
+                continue;
+            }
+
+            // the rollups and index are keyed by app, so the rock comes out of the old ones and goes into the new
+            rollup::remove(&self.db, &mut batch, &rock);
+            index::remove(&self.db, &mut batch, &pk, &rock);
+            write_rock(&self.db, &mut batch, &pk, bincode::serialize(&moved_rock).unwrap(), &moved_rock);
+            moved += 1;
+
+            if moved % BATCH_SIZE == 0 {
+                self.db.write(std::mem::take(&mut batch)).map_err(|err| err.to_string())?;
+            }
+        }
+
+        self.db.write(batch).map_err(|err| err.to_string())?;
+        Ok(moved)
+    }
+
+    fn relabel_downsampled(&self, f: &mut dyn FnMut(&str) -> Option<String>) -> Result<usize, String> {
+        let _rewriting = self.rewriting.lock().unwrap();
+        retention::relabel(&self.db, &mut |app| {
+            let name = self.dict.name(&self.db, dict::Kind::App, app)?;
+            f(&name).map(|to| self.dict.intern(&self.db, dict::Kind::App, &to)).transpose()
+        })
+    }
+
+    fn counts(&self, start: u128, width: u128) -> Result<Vec<Count>, String> {
+        let g = rollup::Granularity::for_width(width);
+
+        let mut counts = Vec::new();
+        for count in rollup::since(&self.db, g, start)? {
+            counts.push(Count {
+                bucket: count.bucket as u128,
+                app: self.dict.name(&self.db, dict::Kind::App, count.app)?,
+                pad: self.dict.name(&self.db, dict::Kind::Pad, count.pad)?,
+                count: count.count.max(0) as u64,
+            });
+        }
+
+        Ok(counts)
+    }
+
+    fn summary(&self, app: &str, start: u128) -> Result<Summary, String> {
+        let mut summary = Summary::default();
+        let Some(id) = self.dict.lookup(&self.db, dict::Kind::App, app)? else {
+            return Ok(summary);
+        };
+
+        // only the rocks for this app, already in bounds
+        let mut failed = None;
+        let mut skipped = Skipped::new("summary");
+        index::scan(&self.db, id, start, u128::MAX, &mut |pk| {
+            match self.get(pk, &mut skipped) {
+                Ok(Some(rock)) => summary.add(&rock.event),
+                Ok(None) => {}
+                Err(err) => {
+                    failed = Some(err);
+                    return false;
+                }
+            }
+            true
+        })?;
+
+        if let Some(err) = failed {
+            return Err(err);
+        }
+
+        // anything older than the retention window only exists as hourly summaries
+        for (_, hour) in retention::since(&self.db, id, start)? {
+            summary.merge(hour);
+        }
+
+        Ok(summary)
+    }
+
+    fn summary_by_pad(&self, app: &str, start: u128) -> Result<HashMap<String, Summary>, String> {
+        let mut summaries = HashMap::<u32, Summary>::new();
+        let Some(id) = self.dict.lookup(&self.db, dict::Kind::App, app)? else {
+            return Ok(HashMap::new());
+        };
+
+        let mut failed = None;
+        let mut skipped = Skipped::new("summary");
+        index::scan(&self.db, id, start, u128::MAX, &mut |pk| {
+            match self.get(pk, &mut skipped) {
+                Ok(Some(rock)) => summaries.entry(rock.pad).or_default().add(&rock.event),
+                Ok(None) => {}
+                Err(err) => {
+                    failed = Some(err);
+                    return false;
+                }
+            }
+            true
+        })?;
+
+        if let Some(err) = failed {
+            return Err(err);
+        }
+
+        let mut by_pad = HashMap::<String, Summary>::new();
+        for (pad, summary) in summaries {
+            by_pad.entry(self.dict.name(&self.db, dict::Kind::Pad, pad)?).or_default().merge(summary);
+        }
+
+        for (pad, hour) in retention::since(&self.db, id, start)? {
+            by_pad.entry(self.dict.name(&self.db, dict::Kind::Pad, pad)?).or_default().merge(hour);
+        }
+
+        Ok(by_pad)
+    }
+
+    fn expire(&self, before: u128) -> Result<usize, String> {
+        let _rewriting = self.rewriting.lock().unwrap();
+        retention::downsample(&self.db, before)
+    }
+
+    fn rebuild(&self) -> Result<usize, String> {
+        let _rewriting = self.rewriting.lock().unwrap();
+        self.rederive()
+    }
+
+    fn backup(&self, dir: &str, keep: u32) -> Result<Backup, String> {
+        backup::create(&self.db, dir, keep)
+    }
+
+    fn check(&self, quarantine: bool) -> Result<Report, String> {
+        let _rewriting = self.rewriting.lock().unwrap();
+        let report = integrity::scan(&self.db, quarantine)?;
+
+        // the quarantined rows were counted and indexed when they were written
+        if report.quarantined > 0 {
+            self.rederive()?;
+        }
+
+        Ok(report)
+    }
+
+    fn identify(&self, app: &str, identity: &Identity) -> Result<(), String> {
+        let id = self.dict.intern(&self.db, dict::Kind::App, app)?;
+        let mut known = self.known(app)?.unwrap_or_default();
+        known.learn(identity);
+        self.dict.describe(&self.db, dict::Kind::App, id, &serde_json::to_vec(&known).map_err(|err| err.to_string())?)
+    }
+
+    fn save_controller(&self, controller: &Controller) -> Result<(), String> {
+        controllers::save(&self.db, controller)
+    }
+
+    fn controllers(&self) -> Result<Vec<Controller>, String> {
+        controllers::all(&self.db)
+    }
+
+    fn known(&self, app: &str) -> Result<Option<Known>, String> {
+        let Some(id) = self.dict.lookup(&self.db, dict::Kind::App, app)? else {
+            return Ok(None);
+        };
+
+        match self.dict.described(&self.db, dict::Kind::App, id)? {
+            Some(known) => serde_json::from_slice(&known).map(Some).map_err(|err| err.to_string()),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{press, Scratch};
+
+    const AT: u128 = 1_700_002_800_000;
+
+    #[test]
+    fn appending_never_overwrites() {
+        let scratch = Scratch::new("nonce");
+        let path = scratch.path("coca-rocks.db");
+        RocksStore::open(&path).unwrap().append(&[press(AT, "game", gilrs::Button::South)]).unwrap();
+
+        // a new run starts the nonce over, like an import of the same moment would
+        let store = RocksStore::open(&path).unwrap();
+        store.append(&[press(AT, "other", gilrs::Button::South)]).unwrap();
+
+        let mut apps = Vec::new();
+        store.scan(0, u128::MAX, &mut |event| {
+            apps.push(event.app);
+            true
+        }).unwrap();
+        apps.sort();
+        assert_eq!(apps, vec!["game".to_string(), "other".to_string()]);
+        assert_eq!(store.counts(0, crate::HOUR).unwrap().iter().map(|count| count.count).sum::<u64>(), 2);
+    }
+}
@@ -0,0 +1,238 @@
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{kind_name, Count, Event, EventStore, Skipped};
+use crate::controllers::Controller;
+use crate::identity::{Identity, Known};
+
+// one plain table, so anyone can poke at it with the sqlite3 cli
+// event is the gilrs::EventType as json, the same as rocks.jsonl
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY,
+    at INTEGER NOT NULL, -- ms since the unix epoch
+    pad TEXT NOT NULL,
+    app TEXT NOT NULL,
+    kind TEXT NOT NULL, -- ButtonPressed, AxisChanged, ...
+    event TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_at ON events (at);
+CREATE INDEX IF NOT EXISTS events_app_at ON events (app, at);
+CREATE TABLE IF NOT EXISTS apps (
+    name TEXT PRIMARY KEY, -- the app column of events
+    known TEXT NOT NULL -- identity::Known as json, the exe, last pid and raw titles
+);
+CREATE TABLE IF NOT EXISTS controllers (
+    id TEXT PRIMARY KEY, -- the pad column of events
+    controller TEXT NOT NULL -- controllers::Controller as json
+);
+";
+
+/// Everything in one sqlite table, slower than rocks but readable with standard tools.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+// sqlite only does i64, which is still a few million years of ms
+fn clamp(at: u128) -> i64 {
+    at.min(i64::MAX as u128) as i64
+}
+
+fn query(conn: &Connection, sql: &str, params: impl rusqlite::Params, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String> {
+    let mut stmt = conn.prepare_cached(sql).map_err(|err| err.to_string())?;
+    let mut rows = stmt.query(params).map_err(|err| err.to_string())?;
+
+    let mut skipped = Skipped::new("sqlite query");
+    while let Some(row) = rows.next().map_err(|err| err.to_string())? {
+        let at: i64 = row.get(0).map_err(|err| err.to_string())?;
+        let event: String = row.get(3).map_err(|err| err.to_string())?;
+
+        // someone poking at the table by hand shouldnt break every read after
+        let event = match serde_json::from_str(&event) {
+            Ok(event) => event,
+            Err(err) => {
+                skipped.add(err);
+                continue;
+            }
+        };
+
+        let event = Event {
+            at: at as u128,
+            pad: row.get(1).map_err(|err| err.to_string())?,
+            app: row.get(2).map_err(|err| err.to_string())?,
+            event,
+        };
+
+        if !f(event) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<SqliteStore, String> {
+        let conn = Connection::open(path).map_err(|err| err.to_string())?;
+        conn.execute_batch(SCHEMA).map_err(|err| err.to_string())?;
+
+        Ok(SqliteStore { conn: Mutex::new(conn) })
+    }
+}
+
+impl EventStore for SqliteStore {
+    fn append(&self, events: &[Event]) -> Result<(), String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|err| err.to_string())?;
+
+        {
+            let mut stmt = tx.prepare_cached("INSERT INTO events (at, pad, app, kind, event) VALUES (?1, ?2, ?3, ?4, ?5)")
+                .map_err(|err| err.to_string())?;
+            for event in events {
+                let json = serde_json::to_string(&event.event).map_err(|err| err.to_string())?;
+                stmt.execute(params![clamp(event.at), event.pad, event.app, kind_name(&event.event), json])
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+
+        tx.commit().map_err(|err| err.to_string())
+    }
+
+    fn scan(&self, start: u128, end: u128, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        query(
+            &conn,
+            "SELECT at, pad, app, event FROM events WHERE at >= ?1 AND at < ?2 ORDER BY at DESC, id DESC",
+            params![clamp(start), clamp(end)],
+            f,
+        )
+    }
+
+    fn scan_app(&self, app: &str, start: u128, end: u128, f: &mut dyn FnMut(Event) -> bool) -> Result<(), String> {
+        let conn = self.conn.lock().unwrap();
+        query(
+            &conn,
+            "SELECT at, pad, app, event FROM events WHERE app = ?1 AND at >= ?2 AND at < ?3 ORDER BY at DESC, id DESC",
+            params![app, clamp(start), clamp(end)],
+            f,
+        )
+    }
+
+    fn delete(&self, start: u128, end: u128) -> Result<usize, String> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM events WHERE at >= ?1 AND at < ?2", params![clamp(start), clamp(end)])
+            .map_err(|err| err.to_string())
+    }
+
+    fn relabel(&self, start: u128, end: u128, f: &mut dyn FnMut(&Event) -> Option<String>) -> Result<usize, String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|err| err.to_string())?;
+
+        // work out every change first, sqlite wont update a table while reading it in the same statement
+        let mut moves = Vec::<(i64, String)>::new();
+        {
+            let mut stmt = tx.prepare_cached("SELECT id, at, pad, app, event FROM events WHERE at >= ?1 AND at < ?2")
+                .map_err(|err| err.to_string())?;
+            let mut rows = stmt.query(params![clamp(start), clamp(end)]).map_err(|err| err.to_string())?;
+
+            let mut skipped = Skipped::new("sqlite relabel");
+            while let Some(row) = rows.next().map_err(|err| err.to_string())? {
+                let json: String = row.get(4).map_err(|err| err.to_string())?;
+                let event = match serde_json::from_str(&json) {
+                    Ok(event) => event,
+                    Err(err) => {
+                        skipped.add(err);
+                        continue;
+                    }
+                };
+
+                let event = Event {
+                    at: row.get::<_, i64>(1).map_err(|err| err.to_string())? as u128,
+                    pad: row.get(2).map_err(|err| err.to_string())?,
+                    app: row.get(3).map_err(|err| err.to_string())?,
+                    event,
+                };
+
+                if let Some(app) = f(&event) {
+                    moves.push((row.get(0).map_err(|err| err.to_string())?, app));
+                }
+            }
+        }
+
+        {
+            let mut stmt = tx.prepare_cached("UPDATE events SET app = ?1 WHERE id = ?2").map_err(|err| err.to_string())?;
+            for (id, app) in &moves {
+                stmt.execute(params![app, id]).map_err(|err| err.to_string())?;
+            }
+        }
+
+        tx.commit().map_err(|err| err.to_string())?;
+        Ok(moves.len())
+    }
+
+    // sqlite can do the bucketing itself, no need to pull every row out
+    fn counts(&self, start: u128, width: u128) -> Result<Vec<Count>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(
+            "SELECT (at / ?2) * ?2 AS bucket, app, pad, COUNT(*) FROM events WHERE at >= ?1 GROUP BY bucket, app, pad ORDER BY bucket DESC",
+        ).map_err(|err| err.to_string())?;
+
+        let rows = stmt.query_map(params![clamp(start), clamp(width.max(1))], |row| {
+            Ok(Count {
+                bucket: row.get::<_, i64>(0)? as u128,
+                app: row.get(1)?,
+                pad: row.get(2)?,
+                count: row.get::<_, i64>(3)? as u64,
+            })
+        }).map_err(|err| err.to_string())?;
+
+        rows.collect::<Result<Vec<Count>, _>>().map_err(|err| err.to_string())
+    }
+
+    fn identify(&self, app: &str, identity: &Identity) -> Result<(), String> {
+        let mut known = self.known(app)?.unwrap_or_default();
+        known.learn(identity);
+
+        let known = serde_json::to_string(&known).map_err(|err| err.to_string())?;
+        self.conn.lock().unwrap()
+            .execute("INSERT INTO apps (name, known) VALUES (?1, ?2) ON CONFLICT (name) DO UPDATE SET known = ?2", params![app, known])
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn known(&self, app: &str) -> Result<Option<Known>, String> {
+        let conn = self.conn.lock().unwrap();
+        let known: Option<String> = conn.query_row("SELECT known FROM apps WHERE name = ?1", params![app], |row| row.get(0))
+            .optional()
+            .map_err(|err| err.to_string())?;
+
+        // same as a bad event, a hand edit shouldnt break anything
+        Ok(known.and_then(|known| serde_json::from_str(&known).ok()))
+    }
+
+    fn save_controller(&self, controller: &Controller) -> Result<(), String> {
+        let json = serde_json::to_string(controller).map_err(|err| err.to_string())?;
+        self.conn.lock().unwrap()
+            .execute("INSERT INTO controllers (id, controller) VALUES (?1, ?2) ON CONFLICT (id) DO UPDATE SET controller = ?2", params![controller.id, json])
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn controllers(&self) -> Result<Vec<Controller>, String> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached("SELECT controller FROM controllers ORDER BY id").map_err(|err| err.to_string())?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0)).map_err(|err| err.to_string())?;
+
+        let mut skipped = Skipped::new("sqlite controllers");
+        let mut controllers = Vec::new();
+        for row in rows {
+            match serde_json::from_str(&row.map_err(|err| err.to_string())?) {
+                Ok(controller) => controllers.push(controller),
+                Err(err) => skipped.add(err),
+            }
+        }
+
+        Ok(controllers)
+    }
+}
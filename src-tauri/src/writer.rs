@@ -0,0 +1,111 @@
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use crate::store::{Event, EventStore};
+
+// how many events the capture thread can get ahead of the writer before it has to wait
+const CHANNEL_SIZE: usize = 4096;
+// write when either of these is hit, whichever is first
+const MAX_BATCH: usize = 1024;
+const MAX_WAIT: Duration = Duration::from_secs(1);
+// dont hang quitting forever if the disk is stuck
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+// a batch that failed is tried again every MAX_WAIT, this many times before its given up on
+const RETRIES: u32 = 30;
+
+enum Msg {
+    Event(Event),
+    Flush(mpsc::Sender<Result<(), String>>),
+}
+
+/// Handle to the writer thread, cheap to clone.
+/// Events are batched up and written every MAX_BATCH events or MAX_WAIT, whichever comes first.
+#[derive(Clone)]
+pub struct Writer {
+    tx: mpsc::SyncSender<Msg>,
+}
+
+struct Stage {
+    store: Arc<dyn EventStore>,
+    pending: Vec<Event>,
+    since: Instant,
+    failures: u32, // in a row, pending is kept until it goes in or RETRIES is hit
+}
+
+impl Stage {
+    fn commit(&mut self) -> Result<(), String> {
+        self.since = Instant::now();
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        match self.store.append(&self.pending) {
+            Ok(()) => {
+                if self.failures > 0 {
+                    log::info!("wrote {} events after {} failed tries", self.pending.len(), self.failures);
+                }
+                self.pending.clear();
+                self.failures = 0;
+                Ok(())
+            }
+            Err(err) => {
+                self.failures += 1;
+                if self.failures >= RETRIES {
+                    log::error!("dropped {} events after {} failed tries: {err}", self.pending.len(), self.failures);
+                    self.pending.clear();
+                    self.failures = 0;
+                } else {
+                    log::warn!("failed to write {} events, trying again: {err}", self.pending.len());
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Writer {
+    pub fn spawn(store: Arc<dyn EventStore>) -> Writer {
+        let (tx, rx) = mpsc::sync_channel(CHANNEL_SIZE);
+
+        std::thread::spawn(move || {
+            let mut stage = Stage { store, pending: Vec::with_capacity(MAX_BATCH), since: Instant::now(), failures: 0 };
+
+            loop {
+                let wait = MAX_WAIT.saturating_sub(stage.since.elapsed());
+                match rx.recv_timeout(wait) {
+                    Ok(Msg::Event(event)) => {
+                        stage.pending.push(event);
+                        // while failing only the timeout tries again, not every event past MAX_BATCH
+                        if stage.pending.len() >= MAX_BATCH && stage.failures == 0 {
+                            let _ = stage.commit();
+                        }
+                    }
+                    Ok(Msg::Flush(done)) => {
+                        let _ = done.send(stage.commit());
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        let _ = stage.commit();
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        let _ = stage.commit();
+                        break;
+                    }
+                }
+            }
+        });
+
+        Writer { tx }
+    }
+
+    /// Queues the event, waits if the writer is too far behind.
+    pub fn send(&self, event: Event) -> Result<(), String> {
+        self.tx.send(Msg::Event(event)).map_err(|_| "writer is gone".to_string())
+    }
+
+    /// Writes everything queued so far, call before quitting.
+    pub fn flush(&self) -> Result<(), String> {
+        let (done, wait) = mpsc::channel();
+        self.tx.send(Msg::Flush(done)).map_err(|_| "writer is gone".to_string())?;
+        wait.recv_timeout(FLUSH_TIMEOUT).map_err(|_| "writer did not flush in time".to_string())?
+    }
+}
@@ -0,0 +1,204 @@
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt, EventMask, Window};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+use crate::identity::Identity;
+
+struct Atoms {
+    active: Atom,
+    name: Atom,
+    pid: Atom,
+    utf8: Atom,
+}
+
+fn atom(conn: &RustConnection, name: &str) -> Result<Atom, String> {
+    Ok(conn.intern_atom(false, name.as_bytes()).map_err(|err| err.to_string())?.reply().map_err(|err| err.to_string())?.atom)
+}
+
+fn active(conn: &RustConnection, atoms: &Atoms, root: Window) -> Result<Window, String> {
+    let reply = conn.get_property(false, root, atoms.active, AtomEnum::WINDOW, 0, 1)
+        .map_err(|err| err.to_string())?
+        .reply()
+        .map_err(|err| err.to_string())?;
+    Ok(reply.value32().and_then(|mut value| value.next()).unwrap_or(0))
+}
+
+// _NET_WM_NAME is utf8, old clients only set WM_NAME
+fn title(conn: &RustConnection, atoms: &Atoms, window: Window) -> Result<String, String> {
+    for (property, kind) in [(atoms.name, atoms.utf8), (AtomEnum::WM_NAME.into(), AtomEnum::ANY.into())] {
+        let reply = conn.get_property(false, window, property, kind, 0, 1024)
+            .map_err(|err| err.to_string())?
+            .reply()
+            .map_err(|err| err.to_string())?;
+        if !reply.value.is_empty() {
+            return Ok(String::from_utf8_lossy(&reply.value).into_owned());
+        }
+    }
+
+    Ok(String::new())
+}
+
+fn focus(conn: &RustConnection, atoms: &Atoms, window: Window) -> Result<Identity, String> {
+    let pid = conn.get_property(false, window, atoms.pid, AtomEnum::CARDINAL, 0, 1)
+        .map_err(|err| err.to_string())?
+        .reply()
+        .map_err(|err| err.to_string())?
+        .value32()
+        .and_then(|mut value| value.next());
+
+    // a pid from another machine (ssh -X) wont be in our /proc, or will be the wrong process
+    let exe = pid
+        .and_then(|pid| std::fs::read_link(format!("/proc/{pid}/exe")).ok())
+        .map(|exe| exe.to_string_lossy().into_owned());
+
+    Ok(Identity { title: title(conn, atoms, window)?, exe, pid })
+}
+
+/// Calls `f` every time the focused window or its title changes, until the display goes away.
+/// The desktop, when nothing is focused, comes through with no title like a window without one.
+/// Needs an ewmh window manager, which is nearly all of them, and works the same on Xvfb.
+/// Under wayland only xwayland windows show up, focusing a native one looks like nothing changed.
+/// Errs straight away if there is no display to connect to.
+pub fn watch(f: &mut dyn FnMut(Identity)) -> Result<(), String> {
+    watch_on(None, f)
+}
+
+// `display` is like ":1", None is whatever DISPLAY says
+fn watch_on(display: Option<&str>, f: &mut dyn FnMut(Identity)) -> Result<(), String> {
+    let (conn, screen) = x11rb::connect(display).map_err(|err| format!("no x display: {err}"))?;
+    let root = conn.setup().roots[screen].root;
+    let atoms = Atoms {
+        active: atom(&conn, "_NET_ACTIVE_WINDOW")?,
+        name: atom(&conn, "_NET_WM_NAME")?,
+        pid: atom(&conn, "_NET_WM_PID")?,
+        utf8: atom(&conn, "UTF8_STRING")?,
+    };
+
+    let listen = |window: Window, mask: EventMask| {
+        conn.change_window_attributes(window, &ChangeWindowAttributesAux::new().event_mask(mask)).map(|_| ()).map_err(|err| err.to_string())
+    };
+
+    // the root says when focus moves, the focused window says when its title changes
+    listen(root, EventMask::PROPERTY_CHANGE)?;
+    let mut window = active(&conn, &atoms, root)?;
+    if window != 0 {
+        listen(window, EventMask::PROPERTY_CHANGE)?;
+    }
+    conn.flush().map_err(|err| err.to_string())?;
+
+    let mut last = None;
+    loop {
+        // a window closing between the event and us asking about it is normal, it just reads as no title
+        let now = if window != 0 { focus(&conn, &atoms, window) } else { Ok(Identity::unknown()) };
+        if let Ok(now) = now {
+            if last.as_ref() != Some(&now) {
+                f(now.clone());
+                last = Some(now);
+            }
+        }
+
+        loop {
+            match conn.wait_for_event().map_err(|err| format!("lost the x display: {err}"))? {
+                Event::PropertyNotify(event) if event.window == root && event.atom == atoms.active => {
+                    let next = active(&conn, &atoms, root)?;
+                    if next == window {
+                        continue;
+                    }
+
+                    // errors here are for windows that are already gone, they come back as events and get ignored
+                    if window != 0 {
+                        let _ = listen(window, EventMask::NO_EVENT);
+                    }
+                    if next != 0 {
+                        let _ = listen(next, EventMask::PROPERTY_CHANGE);
+                    }
+                    conn.flush().map_err(|err| err.to_string())?;
+                    window = next;
+                    break;
+                }
+                Event::PropertyNotify(event) if event.window == window && (event.atom == atoms.name || event.atom == u32::from(AtomEnum::WM_NAME)) => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use x11rb::protocol::xproto::{CreateWindowAux, PropMode, WindowClass};
+    use x11rb::wrapper::ConnectionExt as _;
+
+    use super::*;
+
+    // a display of its own, so the test doesnt depend on (or mess with) the one it runs under
+    struct Xvfb {
+        child: Child,
+        display: String,
+    }
+
+    impl Xvfb {
+        fn start() -> Xvfb {
+            let display = format!(":{}", 100 + std::process::id() % 400);
+            let child = Command::new("Xvfb").arg(&display).stdout(Stdio::null()).stderr(Stdio::null()).spawn()
+                .expect("failed to start Xvfb, install it or leave out --ignored");
+            let xvfb = Xvfb { child, display };
+
+            for _ in 0..50 {
+                if x11rb::connect(Some(&xvfb.display)).is_ok() {
+                    return xvfb;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            panic!("Xvfb never opened {}", xvfb.display);
+        }
+    }
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    #[test]
+    #[ignore = "needs Xvfb, run with --ignored"]
+    fn follows_focus_and_titles() {
+        let xvfb = Xvfb::start();
+
+        let (conn, screen) = x11rb::connect(Some(&xvfb.display)).unwrap();
+        let root = conn.setup().roots[screen].root;
+        let active = atom(&conn, "_NET_ACTIVE_WINDOW").unwrap();
+        let name = atom(&conn, "_NET_WM_NAME").unwrap();
+        let utf8 = atom(&conn, "UTF8_STRING").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let display = xvfb.display.clone();
+        std::thread::spawn(move || watch_on(Some(&display), &mut |focus| {
+            let _ = tx.send(focus);
+        }));
+        let next = || rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // there is no window manager, so nothing has ever been focused
+        assert_eq!(next(), Identity::unknown());
+
+        let window = conn.generate_id().unwrap();
+        conn.create_window(x11rb::COPY_DEPTH_FROM_PARENT, window, root, 0, 0, 10, 10, 0, WindowClass::INPUT_OUTPUT, 0, &CreateWindowAux::new()).unwrap();
+        conn.change_property8(PropMode::REPLACE, window, name, utf8, "game".as_bytes()).unwrap();
+        conn.change_property32(PropMode::REPLACE, root, active, AtomEnum::WINDOW, &[window]).unwrap();
+        conn.flush().unwrap();
+        assert_eq!(next(), Identity::titled("game"));
+
+        conn.change_property8(PropMode::REPLACE, window, name, utf8, "game · level 2".as_bytes()).unwrap();
+        conn.flush().unwrap();
+        assert_eq!(next(), Identity::titled("game · level 2"));
+
+        conn.change_property32(PropMode::REPLACE, root, active, AtomEnum::WINDOW, &[0]).unwrap();
+        conn.flush().unwrap();
+        assert_eq!(next(), Identity::unknown());
+    }
+}